@@ -16,9 +16,19 @@
 struct ReplicationLagTableConfig {
     1: i32 max_replication_lag_allowed_ms,
     2: i32 poll_interval_ms = 2000,
+    // Give up waiting after this many polls with the lag still above the
+    // threshold. Unlimited if unset.
+    3: optional i32 max_polls,
+    // Set to false to skip waiting on this table without removing its config.
+    // Enabled if unset.
+    4: optional bool enabled,
 } (rust.exhaustive)
 
 struct ReplicationLagBlobstoreConfig {
     1: optional ReplicationLagTableConfig sync_queue,
     2: optional ReplicationLagTableConfig xdb_blobstore,
+    // The weight of the lag of each multiplexed blobstore, by blobstore id. The
+    // XDB blobstore waits on the worst weighted lag, so a weight above 1 makes a
+    // blobstore more sensitive and a weight of 0 ignores it. Defaults to 1.
+    3: optional map<i64, double> blobstore_weights,
 } (rust.exhaustive)
@@ -107,6 +107,11 @@ impl EdenFsInstance {
             .map_or(false, |config| config.predictive_prefetching_enabled)
     }
 
+    /// Path of the socket the EdenFS daemon listens on for Thrift requests.
+    pub fn socket_path(&self) -> PathBuf {
+        self.config_dir.join("socket")
+    }
+
     async fn _connect(&self, socket_path: &PathBuf) -> Result<EdenFsClient> {
         let stream = UnixStream::connect(&socket_path)
             .await
@@ -118,8 +123,16 @@ impl EdenFsInstance {
     }
 
     pub async fn connect(&self, timeout: Option<Duration>) -> Result<EdenFsClient> {
-        let socket_path = self.config_dir.join("socket");
+        self.connect_to_socket(self.socket_path(), timeout).await
+    }
 
+    /// Connect to the EdenFS daemon listening on `socket_path` rather than the one of this
+    /// instance.
+    pub async fn connect_to_socket(
+        &self,
+        socket_path: PathBuf,
+        timeout: Option<Duration>,
+    ) -> Result<EdenFsClient> {
         let connect = self._connect(&socket_path);
         let res = if let Some(timeout) = timeout {
             tokio::time::timeout(timeout, connect)
@@ -147,7 +160,7 @@ impl EdenFsInstance {
         &self,
         timeout: Option<Duration>,
     ) -> Result<StreamingEdenFsClient> {
-        let socket_path = self.config_dir.join("socket");
+        let socket_path = self.socket_path();
         let client = self._connect_streaming(&socket_path);
 
         if let Some(timeout) = timeout {
@@ -108,7 +108,7 @@ impl AggregatedUsageCounts {
     }
 }
 
-fn format_size(size: u64) -> String {
+pub(crate) fn format_size(size: u64) -> String {
     if size > 1000000000 {
         format!("{:.1} GB", size as f64 / 1000000000.0)
     } else if size > 1000000 {
@@ -8,18 +8,34 @@
 //! edenfsctl minitop
 
 use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::ffi::OsString;
+use std::fs::File;
 use std::io::stdout;
+use std::io::BufWriter;
 use std::io::Stdout;
 use std::io::Write;
 use std::path::Path;
+use std::path::PathBuf;
 use std::time::Duration;
 use std::time::Instant;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
 
 use anyhow::anyhow;
 use anyhow::Result;
 use async_trait::async_trait;
+use chrono::DateTime;
+use chrono::Local;
+use chrono::SecondsFormat;
+use clap::ArgEnum;
 use clap::Parser;
+use comfy_table::presets::ASCII_BORDERS_ONLY;
 use comfy_table::presets::UTF8_BORDERS_ONLY;
+use comfy_table::Attribute;
+use comfy_table::Cell;
+use comfy_table::Color;
+use comfy_table::ContentArrangement;
 use comfy_table::Table;
 use crossterm::cursor;
 use crossterm::event::Event;
@@ -30,6 +46,7 @@ use crossterm::event::KeyModifiers;
 use crossterm::queue;
 use crossterm::style;
 use crossterm::terminal;
+use crossterm::tty::IsTty;
 use edenfs_client::EdenFsClient;
 use edenfs_client::EdenFsInstance;
 use edenfs_utils::humantime::HumanTime;
@@ -37,14 +54,22 @@ use edenfs_utils::humantime::TimeUnit;
 use edenfs_utils::path_from_bytes;
 use futures::FutureExt;
 use futures::StreamExt;
+use rusqlite::params;
+use rusqlite::Connection;
 use shlex::quote;
 use sysinfo::Pid;
+use sysinfo::ProcessExt;
 use sysinfo::System;
 use sysinfo::SystemExt;
 use thrift_types::edenfs::types::pid_t;
 use thrift_types::edenfs::types::AccessCounts;
 use thrift_types::edenfs::types::GetAccessCountsResult;
+use tracing::event;
+use tracing::Level;
+use unicode_width::UnicodeWidthChar;
+use unicode_width::UnicodeWidthStr;
 
+use crate::du::format_size;
 use crate::ExitCode;
 
 #[derive(Parser, Debug)]
@@ -58,20 +83,406 @@ pub struct MinitopCmd {
         short,
         help = "Specify the rate (in seconds) at which eden top updates.",
         default_value = "1",
-        parse(from_str = parse_refresh_rate),
+        parse(try_from_str = parse_refresh_rate),
     )]
     refresh_rate: Duration,
 
+    #[clap(
+        long,
+        help = "Connect to the EdenFS daemon listening on this socket instead of the one in the \
+            config directory (see --config-dir).",
+        parse(from_os_str)
+    )]
+    socket: Option<PathBuf>,
+
     #[clap(long, help = "Enable minitop interactive mode.")]
     interactive: bool,
+
+    #[clap(
+        long,
+        help = "Write the aggregated counts to this path in the Prometheus textfile format on \
+            every refresh.",
+        parse(from_os_str)
+    )]
+    prometheus: Option<PathBuf>,
+
+    #[clap(
+        long,
+        help = "Append the aggregated counts to this SQLite database on every refresh, creating \
+            it if needed.",
+        parse(from_os_str)
+    )]
+    record: Option<PathBuf>,
+
+    #[clap(long, help = "Exit after refreshing the counts this many times.")]
+    iterations: Option<u64>,
+
+    #[clap(
+        long,
+        help = "Print counts as plain integers instead of human-readable values."
+    )]
+    raw: bool,
+
+    #[clap(
+        long,
+        help = "Highlight processes whose fetches or backing store imports exceed this value.",
+        default_value = "1000"
+    )]
+    highlight_threshold: i64,
+
+    #[clap(
+        long,
+        help = "Hide processes with fewer filesystem requests than this value.",
+        default_value = "0"
+    )]
+    min_fuse_count: i64,
+
+    #[clap(
+        long,
+        help = "Label the import columns by where imports are served from (memory cache, disk \
+            cache or backing store) and show their exact counts."
+    )]
+    imports_detail: bool,
+
+    #[clap(
+        long,
+        help = "Aggregate processes running the same command across all mounts."
+    )]
+    by_cmd: bool,
+
+    #[clap(long, help = "Show a subtotal row after the processes of each mount.")]
+    subtotals: bool,
+
+    #[clap(long, help = "Disable colored output.")]
+    no_color: bool,
+
+    #[clap(
+        long,
+        help = "Don't print the row with the column titles, eg: when parsing the output."
+    )]
+    no_header: bool,
+
+    #[clap(
+        long,
+        help = "Don't truncate the CMD column to fit the terminal width."
+    )]
+    full_cmd: bool,
+
+    #[clap(
+        long,
+        conflicts_with = "columns",
+        help = "Show all the default columns without truncating or wrapping them, so that every \
+            process is on a single line even if it overflows the terminal."
+    )]
+    wide: bool,
+
+    #[clap(
+        long,
+        help = "Show the counts accumulated during the last refresh interval instead of since \
+            minitop started."
+    )]
+    delta: bool,
+
+    #[clap(
+        long,
+        arg_enum,
+        value_delimiter = ',',
+        help = "Comma-separated list of columns to display, in order."
+    )]
+    columns: Vec<Column>,
+
+    #[clap(
+        long,
+        arg_enum,
+        default_value_t = TimeFormat::Relative,
+        help = "How to display times: relative to now, as an ISO 8601 timestamp, or as seconds \
+            since the Unix epoch."
+    )]
+    time_format: TimeFormat,
+
+    #[clap(
+        long,
+        arg_enum,
+        default_value_t = ThemeName::Default,
+        help = "Color theme of the table. --no-color takes precedence over it."
+    )]
+    theme: ThemeName,
+}
+
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum TimeFormat {
+    Relative,
+    Iso,
+    Seconds,
 }
 
-fn parse_refresh_rate(arg: &str) -> Duration {
-    let seconds = arg
-        .parse::<u64>()
-        .expect("Please enter a valid whole positive number for refresh_rate.");
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum ThemeName {
+    Default,
+    Mono,
+    HighContrast,
+}
+
+/// How the table is drawn and which colors are used to highlight processes.
+struct Theme {
+    preset: &'static str,
+    /// Processes with more fetches or imports than `--highlight-threshold`.
+    high_activity: Option<Color>,
+    /// Processes with more than half of `--highlight-threshold`.
+    medium_activity: Option<Color>,
+}
 
-    Duration::new(seconds, 0)
+impl ThemeName {
+    fn theme(&self) -> Theme {
+        match self {
+            ThemeName::Default => Theme {
+                preset: UTF8_BORDERS_ONLY,
+                high_activity: Some(Color::Red),
+                medium_activity: Some(Color::Yellow),
+            },
+            ThemeName::Mono => Theme {
+                preset: ASCII_BORDERS_ONLY,
+                high_activity: None,
+                medium_activity: None,
+            },
+            // Readable on both light and dark backgrounds.
+            ThemeName::HighContrast => Theme {
+                preset: UTF8_BORDERS_ONLY,
+                high_activity: Some(Color::Magenta),
+                medium_activity: Some(Color::Blue),
+            },
+        }
+    }
+}
+
+impl MinitopCmd {
+    fn columns(&self) -> &[Column] {
+        if self.columns.is_empty() {
+            DEFAULT_COLUMNS
+        } else {
+            &self.columns
+        }
+    }
+
+    /// Render the content of `column` for an aggregated process.
+    fn render_column(
+        &self,
+        column: Column,
+        process: &Process,
+        context: &RenderContext,
+    ) -> Result<String> {
+        Ok(match column {
+            Column::Pid => process.pid.to_string(),
+            Column::Mount => process.mount_name.clone(),
+            Column::Reads => self.format_count(process.access_counts.fsChannelReads),
+            Column::Writes => self.format_count(process.access_counts.fsChannelWrites),
+            Column::FuseCount => self.format_count(process.access_counts.fsChannelTotal),
+            Column::Fetches => self.format_count(process.fetch_counts),
+            Column::Memory => {
+                self.format_import_count(process.access_counts.fsChannelMemoryCacheImports)
+            }
+            Column::Disk => {
+                self.format_import_count(process.access_counts.fsChannelDiskCacheImports)
+            }
+            Column::Imports => {
+                self.format_import_count(process.access_counts.fsChannelBackingStoreImports)
+            }
+            Column::TimeSpent => HumanTime::from(Duration::from_nanos(
+                process.access_counts.fsChannelDurationNs.try_into()?,
+            ))
+            .simple_human_time(TimeUnit::Nanoseconds),
+            Column::LastAccess => self.format_time(process.last_access_time),
+            Column::Start => process
+                .start_time
+                .map_or_else(|| String::from("-"), |time| self.format_time(time)),
+            Column::Cmd => process.cmd.clone(),
+            Column::ReadRate => format!(
+                "{:.1}",
+                process.delta().access_counts.fsChannelReads as f64
+                    / context.interval.as_secs_f64()
+            ),
+            Column::WriteRate => format!(
+                "{:.1}",
+                process.delta().access_counts.fsChannelWrites as f64
+                    / context.interval.as_secs_f64()
+            ),
+            Column::FetchPercent => {
+                if context.total_fetch_counts == 0 {
+                    String::from("0.0%")
+                } else {
+                    format!(
+                        "{:.1}%",
+                        process.fetch_counts as f64 * 100.0 / context.total_fetch_counts as f64
+                    )
+                }
+            }
+            // Many fetches for few actual imports hints at cache thrashing.
+            Column::FetchRatio => {
+                let imports = process.access_counts.fsChannelBackingStoreImports;
+                if imports == 0 {
+                    String::from("-")
+                } else {
+                    format!("{:.1}", process.fetch_counts as f64 / imports as f64)
+                }
+            }
+        })
+    }
+
+    /// Render the content of `column` for the row totaling the aggregated processes of a mount.
+    fn render_subtotal_column(
+        &self,
+        column: Column,
+        subtotal: &Process,
+        context: &RenderContext,
+    ) -> Result<String> {
+        Ok(match column {
+            Column::Pid => String::from("SUBTOTAL"),
+            Column::Mount => subtotal.mount_name.clone(),
+            _ => self.render_total_column(column, subtotal, context)?,
+        })
+    }
+
+    /// Render the content of `column` for the row totaling all the aggregated processes.
+    fn render_total_column(
+        &self,
+        column: Column,
+        total: &Process,
+        context: &RenderContext,
+    ) -> Result<String> {
+        Ok(match column {
+            Column::Pid => String::from("TOTAL"),
+            Column::Mount | Column::LastAccess | Column::Start | Column::Cmd => String::new(),
+            _ => self.render_column(column, total, context)?,
+        })
+    }
+
+    fn format_time(&self, time: SystemTime) -> String {
+        match self.time_format {
+            TimeFormat::Relative => HumanTime::from(time.elapsed().unwrap_or_default())
+                .simple_human_time(TimeUnit::Seconds),
+            TimeFormat::Iso => {
+                DateTime::<Local>::from(time).to_rfc3339_opts(SecondsFormat::Secs, false)
+            }
+            TimeFormat::Seconds => format!(
+                "{:.3}",
+                time.duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs_f64()
+            ),
+        }
+    }
+
+    fn format_count(&self, count: i64) -> String {
+        if self.raw {
+            count.to_string()
+        } else {
+            format_with_separators(count)
+        }
+    }
+
+    fn column_title(&self, column: Column) -> &'static str {
+        match column {
+            Column::Memory if self.imports_detail => "MEM IMP",
+            Column::Disk if self.imports_detail => "DISK IMP",
+            Column::Imports if self.imports_detail => "BACKING IMP",
+            _ => column.title(),
+        }
+    }
+
+    /// Titles of the displayed columns, unless the header row is disabled.
+    fn header(&self) -> Option<Vec<&'static str>> {
+        if self.no_header {
+            None
+        } else {
+            Some(
+                self.columns()
+                    .iter()
+                    .map(|column| self.column_title(*column))
+                    .collect(),
+            )
+        }
+    }
+
+    fn format_import_count(&self, count: i64) -> String {
+        if self.raw || self.imports_detail {
+            self.format_count(count)
+        } else {
+            format_with_suffix(count)
+        }
+    }
+
+    /// Render the summary line shown above the table, similar to the one at the top of `top(1)`.
+    fn render_summary(
+        &self,
+        processes: &TrackedProcesses,
+        refresh_rate: Duration,
+        interval: Duration,
+    ) -> String {
+        let mounts = processes
+            .values()
+            .map(|process| process.mount_name.as_str())
+            .collect::<BTreeSet<&str>>();
+        let interval_ops: i64 = processes
+            .values()
+            .map(|process| process.delta().access_counts.fsChannelTotal)
+            .sum();
+        format!(
+            "mounts: {}  processes: {}  fs ops this interval: {}  refresh: {:.1}s (actual {:.1}s)",
+            mounts.len(),
+            processes.len(),
+            self.format_count(interval_ops),
+            refresh_rate.as_secs_f64(),
+            interval.as_secs_f64()
+        )
+    }
+
+    /// Pick the color used to render a process with a lot of backing store activity, if any.
+    fn highlight_color(&self, theme: &Theme, process: &Process) -> Option<Color> {
+        let activity = std::cmp::max(
+            process.fetch_counts,
+            process.access_counts.fsChannelBackingStoreImports,
+        );
+        if activity > self.highlight_threshold {
+            theme.high_activity
+        } else if activity > self.highlight_threshold / 2 {
+            theme.medium_activity
+        } else {
+            None
+        }
+    }
+}
+
+fn parse_refresh_rate(arg: &str) -> Result<Duration, String> {
+    match arg.parse::<u64>() {
+        Ok(seconds) if seconds > 0 => Ok(Duration::from_secs(seconds)),
+        _ => Err(String::from(
+            "refresh rate must be a positive number of seconds",
+        )),
+    }
+}
+
+/// Bounds of the refresh rate when changing it with `+` and `-`.
+///
+/// The daemon reports accesses over a whole number of seconds, so the refresh rate is kept to
+/// whole seconds too: each refresh then queries exactly the accesses since the previous one.
+const MIN_REFRESH_RATE: Duration = Duration::from_secs(1);
+const MAX_REFRESH_RATE: Duration = Duration::from_secs(60);
+
+fn faster_refresh_rate(refresh_rate: Duration) -> Duration {
+    std::cmp::max(
+        Duration::from_secs(refresh_rate.as_secs() / 2),
+        MIN_REFRESH_RATE,
+    )
+}
+
+fn slower_refresh_rate(refresh_rate: Duration) -> Duration {
+    std::cmp::min(refresh_rate * 2, MAX_REFRESH_RATE)
+}
+
+/// Duration, in whole seconds, to pass to `getAccessCounts` for a refresh rate.
+fn access_counts_duration(refresh_rate: Duration) -> i64 {
+    let seconds = refresh_rate.as_secs() + u64::from(refresh_rate.subsec_nanos() > 0);
+    std::cmp::max(seconds, 1).try_into().unwrap_or(i64::MAX)
 }
 
 const PENDING_COUNTER_REGEX: &str = r"store\.hg\.pending_import\..*";
@@ -80,19 +491,220 @@ const IMPORT_OBJECT_TYPES: &[&str] = &["blob", "tree"];
 const STATS_NOT_AVAILABLE: i64 = 0;
 
 const UNKNOWN_COMMAND: &str = "<unknown>";
-const COLUMN_TITLES: &[&str] = &[
-    "PID",
-    "MOUNT",
-    "READS",
-    "WRITES",
-    "TOTAL COUNT",
-    "FETCHES",
-    "MEMORY",
-    "DISK",
-    "IMPORTS",
-    "TIME SPENT",
-    "LAST ACCESS",
-    "CMD",
+
+/// Returned when the EdenFS daemon isn't running. This is `EX_UNAVAILABLE` from `sysexits.h`,
+/// which can't be confused with the exit code of 2 used for invalid arguments.
+const EDENFS_NOT_RUNNING_EXIT_CODE: ExitCode = 69;
+
+/// Test if connecting to the daemon failed because nothing is listening on its socket, as
+/// opposed to any other error which may happen while the daemon is running.
+fn is_not_running_error(error: &anyhow::Error) -> bool {
+    error
+        .chain()
+        .filter_map(|cause| cause.downcast_ref::<std::io::Error>())
+        .any(|error| {
+            matches!(
+                error.kind(),
+                std::io::ErrorKind::ConnectionRefused | std::io::ErrorKind::NotFound
+            )
+        })
+}
+
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Column {
+    Pid,
+    Mount,
+    Reads,
+    Writes,
+    FuseCount,
+    Fetches,
+    Memory,
+    Disk,
+    Imports,
+    TimeSpent,
+    LastAccess,
+    Cmd,
+    ReadRate,
+    WriteRate,
+    FetchPercent,
+    Start,
+    FetchRatio,
+}
+
+impl Column {
+    fn title(&self) -> &'static str {
+        match self {
+            Column::Pid => "PID",
+            Column::Mount => "MOUNT",
+            Column::Reads => "READS",
+            Column::Writes => "WRITES",
+            Column::FuseCount => "TOTAL COUNT",
+            Column::Fetches => "FETCHES",
+            Column::Memory => "MEMORY",
+            Column::Disk => "DISK",
+            Column::Imports => "IMPORTS",
+            Column::TimeSpent => "TIME SPENT",
+            Column::LastAccess => "LAST ACCESS",
+            Column::Cmd => "CMD",
+            Column::ReadRate => "FS R/s",
+            Column::WriteRate => "FS W/s",
+            Column::FetchPercent => "% FETCH",
+            Column::Start => "START",
+            Column::FetchRatio => "FETCH/IMP",
+        }
+    }
+}
+
+/// How a row of the table should be styled.
+#[derive(Clone, Copy)]
+enum RowStyle {
+    Normal,
+    Highlighted(Color),
+    /// The process shown on this row is no longer running.
+    Dead,
+    Subtotal,
+    Total,
+}
+
+struct Row {
+    cells: Vec<String>,
+    style: RowStyle,
+}
+
+impl Row {
+    fn into_cells(self, color_enabled: bool) -> Vec<Cell> {
+        let style = self.style;
+        self.cells
+            .into_iter()
+            .map(|content| {
+                let cell = Cell::new(content);
+                if !color_enabled {
+                    return cell;
+                }
+                match style {
+                    RowStyle::Normal => cell,
+                    RowStyle::Highlighted(color) => cell.fg(color),
+                    RowStyle::Dead => cell.add_attribute(Attribute::Dim),
+                    RowStyle::Subtotal => cell.add_attribute(Attribute::Italic),
+                    RowStyle::Total => cell.add_attribute(Attribute::Bold),
+                }
+            })
+            .collect()
+    }
+}
+
+/// Truncate the content of the `column`th column of `rows` so that the table fits within `width`
+/// display columns.
+fn truncate_column(header: Option<&[&str]>, rows: &mut [Row], column: usize, width: usize) {
+    // Each column is padded with a space on both sides, and the table has a border on both sides.
+    let column_width = |index: usize| {
+        rows.iter()
+            .map(|row| row.cells[index].width())
+            .chain(header.map(|header| header[index].width()))
+            .max()
+            .unwrap_or(0)
+            + 2
+    };
+    let num_columns = rows.first().map_or(0, |row| row.cells.len());
+    let other_columns_width: usize = (0..num_columns)
+        .filter(|index| *index != column)
+        .map(column_width)
+        .sum();
+    let available_width = width.saturating_sub(other_columns_width + 2 + 2);
+
+    for row in rows.iter_mut() {
+        row.cells[column] = truncate_with_ellipsis(&row.cells[column], available_width);
+    }
+}
+
+/// Truncate `content` to at most `max_width` display columns, replacing the end of the string
+/// with an ellipsis when it doesn't fit.
+fn truncate_with_ellipsis(content: &str, max_width: usize) -> String {
+    if content.width() <= max_width {
+        return content.to_string();
+    }
+
+    let mut truncated = String::new();
+    let mut truncated_width = 0;
+    for c in content.chars() {
+        let char_width = c.width().unwrap_or(0);
+        // Keep one column for the ellipsis.
+        if truncated_width + char_width + 1 > max_width {
+            break;
+        }
+        truncated.push(c);
+        truncated_width += char_width;
+    }
+    if max_width > 0 {
+        truncated.push('\u{2026}');
+    }
+    truncated
+}
+
+/// Data shared by all the rows rendered during a refresh.
+struct RenderContext {
+    /// Wall time elapsed between the last two refreshes.
+    interval: Duration,
+    /// Sum of the fetch counts of all the aggregated processes.
+    total_fetch_counts: i64,
+}
+
+const DEFAULT_COLUMNS: &[Column] = &[
+    Column::Pid,
+    Column::Mount,
+    Column::Reads,
+    Column::Writes,
+    Column::FuseCount,
+    Column::Fetches,
+    Column::Memory,
+    Column::Disk,
+    Column::Imports,
+    Column::TimeSpent,
+    Column::LastAccess,
+    Column::Cmd,
+];
+
+/// Metrics written by `--prometheus`, along with how to read each of them from an aggregated
+/// `Process`.
+///
+/// Despite their names, these cover all the filesystem channels (FUSE, NFS and PrjFS).
+const PROMETHEUS_METRICS: &[(&str, &str, fn(&Process) -> i64)] = &[
+    ("eden_fuse_reads_total", "Number of filesystem reads", |p| {
+        p.access_counts.fsChannelReads
+    }),
+    (
+        "eden_fuse_writes_total",
+        "Number of filesystem writes",
+        |p| p.access_counts.fsChannelWrites,
+    ),
+    (
+        "eden_fuse_requests_total",
+        "Total number of filesystem requests",
+        |p| p.access_counts.fsChannelTotal,
+    ),
+    ("eden_fuse_fetches_total", "Number of fetches", |p| {
+        p.fetch_counts
+    }),
+    (
+        "eden_fuse_memory_cache_imports_total",
+        "Number of imports served from the memory cache",
+        |p| p.access_counts.fsChannelMemoryCacheImports,
+    ),
+    (
+        "eden_fuse_disk_cache_imports_total",
+        "Number of imports served from the disk cache",
+        |p| p.access_counts.fsChannelDiskCacheImports,
+    ),
+    (
+        "eden_fuse_backing_imports_total",
+        "Number of imports from the backing store",
+        |p| p.access_counts.fsChannelBackingStoreImports,
+    ),
+    (
+        "eden_fuse_duration_ns_total",
+        "Time spent serving filesystem requests, in nanoseconds",
+        |p| p.access_counts.fsChannelDurationNs,
+    ),
 ];
 
 trait GetAccessCountsResultExt {
@@ -103,7 +715,9 @@ impl GetAccessCountsResultExt for GetAccessCountsResult {
     fn get_cmd_for_pid(&self, pid: pid_t) -> Result<String> {
         match self.cmdsByPid.get(&pid) {
             Some(cmd) => {
-                let cmd = String::from_utf8(cmd.to_vec())?;
+                // A command line isn't guaranteed to be valid UTF-8, but it's more useful to show
+                // it with replacement characters than to not show it at all.
+                let cmd = String::from_utf8_lossy(cmd);
 
                 // remove trailing null which would cause the command to show up with an
                 // extra empty string on the end
@@ -116,7 +730,8 @@ impl GetAccessCountsResultExt for GetAccessCountsResult {
                         .file_name()
                         .ok_or_else(|| anyhow!("cmd filename is missing"))?
                         .to_str()
-                        .ok_or_else(|| anyhow!("cmd is not UTF-8"))?;
+                        // The path was made from a `str`, so its file name is valid UTF-8.
+                        .expect("cmd is UTF-8");
                 }
 
                 Ok(parts
@@ -140,39 +755,82 @@ impl GetAccessCountsResultExt for GetAccessCountsResult {
 
 trait AccessCountsExt {
     fn add(&mut self, other: &AccessCounts);
+    fn sub(&mut self, other: &AccessCounts);
 }
 
+// Counts are accumulated for as long as minitop runs, saturate rather than overflow.
 impl AccessCountsExt for AccessCounts {
     fn add(&mut self, other: &AccessCounts) {
-        self.fsChannelTotal += other.fsChannelTotal;
-        self.fsChannelReads += other.fsChannelReads;
-        self.fsChannelWrites += other.fsChannelWrites;
-        self.fsChannelBackingStoreImports += other.fsChannelBackingStoreImports;
-        self.fsChannelDurationNs += other.fsChannelDurationNs;
-        self.fsChannelMemoryCacheImports += other.fsChannelMemoryCacheImports;
-        self.fsChannelDiskCacheImports += other.fsChannelDiskCacheImports;
+        self.fsChannelTotal = self.fsChannelTotal.saturating_add(other.fsChannelTotal);
+        self.fsChannelReads = self.fsChannelReads.saturating_add(other.fsChannelReads);
+        self.fsChannelWrites = self.fsChannelWrites.saturating_add(other.fsChannelWrites);
+        self.fsChannelBackingStoreImports = self
+            .fsChannelBackingStoreImports
+            .saturating_add(other.fsChannelBackingStoreImports);
+        self.fsChannelDurationNs = self
+            .fsChannelDurationNs
+            .saturating_add(other.fsChannelDurationNs);
+        self.fsChannelMemoryCacheImports = self
+            .fsChannelMemoryCacheImports
+            .saturating_add(other.fsChannelMemoryCacheImports);
+        self.fsChannelDiskCacheImports = self
+            .fsChannelDiskCacheImports
+            .saturating_add(other.fsChannelDiskCacheImports);
+    }
+
+    fn sub(&mut self, other: &AccessCounts) {
+        self.fsChannelTotal = self.fsChannelTotal.saturating_sub(other.fsChannelTotal);
+        self.fsChannelReads = self.fsChannelReads.saturating_sub(other.fsChannelReads);
+        self.fsChannelWrites = self.fsChannelWrites.saturating_sub(other.fsChannelWrites);
+        self.fsChannelBackingStoreImports = self
+            .fsChannelBackingStoreImports
+            .saturating_sub(other.fsChannelBackingStoreImports);
+        self.fsChannelDurationNs = self
+            .fsChannelDurationNs
+            .saturating_sub(other.fsChannelDurationNs);
+        self.fsChannelMemoryCacheImports = self
+            .fsChannelMemoryCacheImports
+            .saturating_sub(other.fsChannelMemoryCacheImports);
+        self.fsChannelDiskCacheImports = self
+            .fsChannelDiskCacheImports
+            .saturating_sub(other.fsChannelDiskCacheImports);
     }
 }
 
 #[derive(Clone)]
 struct Process {
     pid: pid_t,
+    tgid: Option<pid_t>,
+    start_time: Option<SystemTime>,
     mount_name: String,
     cmd: String,
     access_counts: AccessCounts,
     fetch_counts: i64,
-    last_access_time: Instant,
+    last_access_time: SystemTime,
+    /// Counts at the start of the current refresh interval, used to compute deltas.
+    previous_access_counts: AccessCounts,
+    previous_fetch_counts: i64,
 }
 
 impl Process {
-    fn new(pid: pid_t, mount_name: String) -> Self {
+    fn new(
+        pid: pid_t,
+        tgid: Option<pid_t>,
+        mount_name: String,
+        start_time: Option<SystemTime>,
+        now: SystemTime,
+    ) -> Self {
         Self {
             pid,
+            tgid,
+            start_time,
             mount_name,
             cmd: "<unknown>".to_string(),
             access_counts: AccessCounts::default(),
             fetch_counts: 0,
-            last_access_time: Instant::now(),
+            last_access_time: now,
+            previous_access_counts: AccessCounts::default(),
+            previous_fetch_counts: 0,
         }
     }
 
@@ -185,29 +843,84 @@ impl Process {
     ///
     /// Since the `getAccessCounts` API gives us an incremental `AccessCounts`, this is simply
     /// incrementing the current counts with the passed ones. The last access time is also
-    /// incremented.
-    fn increment_access_counts(&mut self, counts: &AccessCounts) {
+    /// updated to `now`.
+    fn increment_access_counts(&mut self, counts: &AccessCounts, now: SystemTime) {
         self.access_counts.add(counts);
-        self.last_access_time = Instant::now();
+        self.last_access_time = now;
     }
 
     /// Update this `Process` fetch counts.
     ///
     /// As opposed to the access counts, this is an absolute value since EdenFS started, thus this
     /// will only update the last access time if the fetch counts also changed.
-    fn set_fetch_counts(&mut self, fetch_counts: i64) {
+    fn set_fetch_counts(&mut self, fetch_counts: i64, now: SystemTime) {
         if self.fetch_counts != fetch_counts {
             self.fetch_counts = fetch_counts;
-            self.last_access_time = Instant::now();
+            self.last_access_time = now;
         }
     }
 
+    /// Snapshot the current counts as the start of a new refresh interval.
+    fn start_interval(&mut self) {
+        self.previous_access_counts = self.access_counts.clone();
+        self.previous_fetch_counts = self.fetch_counts;
+    }
+
+    /// Get a copy of this `Process` with only the counts accumulated during the current refresh
+    /// interval.
+    fn delta(&self) -> Self {
+        let mut delta = self.clone();
+        delta.access_counts.sub(&self.previous_access_counts);
+        delta.fetch_counts -= self.previous_fetch_counts;
+        delta.previous_access_counts = AccessCounts::default();
+        delta.previous_fetch_counts = 0;
+        delta
+    }
+
     /// Test if this `Process` is still running.
+    ///
+    /// This relies on the process list maintained by `sysinfo`, which is refreshed once per
+    /// iteration and works the same way on Linux, macOS and Windows.
     fn is_running(&self, system: &System) -> bool {
         system.process(self.pid as Pid).is_some()
     }
 }
 
+/// Get the time at which `pid` started, this relies on `system` having been refreshed since the
+/// process was started.
+fn get_start_time(system: &System, pid: pid_t) -> Option<SystemTime> {
+    system
+        .process(pid as Pid)
+        .map(|process| UNIX_EPOCH + Duration::from_secs(process.start_time()))
+}
+
+/// Get the thread group id of `pid`, ie: the pid of the process that the thread belongs to.
+#[cfg(target_os = "linux")]
+fn get_tgid(pid: pid_t) -> Option<pid_t> {
+    let status = std::fs::read_to_string(format!("/proc/{}/status", pid)).ok()?;
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("Tgid:"))
+        .and_then(|tgid| tgid.trim().parse().ok())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn get_tgid(_pid: pid_t) -> Option<pid_t> {
+    None
+}
+
+/// Render the memory and CPU usage of the EdenFS daemon itself, if it is still running.
+fn render_daemon_usage(system: &System, daemon_pid: pid_t) -> Option<String> {
+    let process = system.process(daemon_pid as Pid)?;
+    // sysinfo reports the memory usage in KiB.
+    Some(format!(
+        "edenfs (pid {}): rss {}  cpu {:.1}%",
+        daemon_pid,
+        format_size(process.memory() * 1024),
+        process.cpu_usage()
+    ))
+}
+
 /// Get the last component of the passed in byte slice representing a Path.
 ///
 /// The path is eagerly converted from an `OsString` to a `String` for ease of use.
@@ -225,21 +938,107 @@ fn get_mount_name(mount_path: &[u8]) -> anyhow::Result<String> {
 
 type TrackedProcesses = BTreeMap<pid_t, Process>;
 
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+enum AggregationKey<'a> {
+    /// (mount, tgid)
+    Tgid(&'a str, pid_t),
+    /// (mount, cmd)
+    Cmd(&'a str, &'a str),
+    /// cmd, regardless of the mount it accessed
+    AllMounts(&'a str),
+}
+
+impl<'a> AggregationKey<'a> {
+    fn new(process: &'a Process, by_cmd: bool) -> Self {
+        match process.tgid {
+            _ if by_cmd => AggregationKey::AllMounts(&process.cmd),
+            Some(tgid) => AggregationKey::Tgid(&process.mount_name, tgid),
+            None => AggregationKey::Cmd(&process.mount_name, &process.cmd),
+        }
+    }
+}
+
+/// Shown in the MOUNT column of processes aggregated across several mounts.
+const ALL_MOUNTS: &str = "*";
+
+/// Update currently tracked processes (and add new ones if they haven't been tracked yet).
+///
+/// The counts are reported the same way whether a mount uses FUSE, NFS or PrjFS. Processes that
+/// accessed EdenFS are marked as last accessing it at `now`, and new ones get their TGID from
+/// `get_tgid`.
+fn update_processes(
+    tracked_processes: &mut TrackedProcesses,
+    counts: &GetAccessCountsResult,
+    system: &System,
+    get_tgid: impl Fn(pid_t) -> Option<pid_t>,
+    now: SystemTime,
+) -> Result<()> {
+    for (mount, accesses) in &counts.accessesByMount {
+        let mount_name = get_mount_name(mount)?;
+
+        for (pid, access_counts) in &accesses.accessCountsByPid {
+            tracked_processes
+                .entry(*pid)
+                .or_insert_with(|| {
+                    Process::new(
+                        *pid,
+                        get_tgid(*pid),
+                        mount_name.clone(),
+                        get_start_time(system, *pid),
+                        now,
+                    )
+                })
+                .set_cmd(counts.get_cmd_for_pid(*pid)?)
+                .increment_access_counts(access_counts, now);
+        }
+
+        for (pid, fetch_counts) in &accesses.fetchCountsByPid {
+            tracked_processes
+                .entry(*pid)
+                .or_insert_with(|| {
+                    Process::new(
+                        *pid,
+                        get_tgid(*pid),
+                        mount_name.clone(),
+                        get_start_time(system, *pid),
+                        now,
+                    )
+                })
+                .set_cmd(counts.get_cmd_for_pid(*pid)?)
+                .set_fetch_counts(*fetch_counts, now);
+        }
+    }
+
+    Ok(())
+}
+
 /// We aggregate all tracked processes in a separate step right before rendering
-/// (as opposed to aggregating eagerly as we receive process logs in `update_process`)
+/// (as opposed to aggregating eagerly as we receive process logs in `update_processes`)
 /// because tracked processes could stop running which may change the top_pid.
-fn aggregate_processes(processes: &TrackedProcesses, system: &System) -> Vec<Process> {
-    // Technically, it's more correct to aggregate this by TGID
-    // Because that's hard to get, we instead aggregate by mount & cmd
-    // (mount, cmd) => Process
-    let mut aggregated_processes = BTreeMap::<(&str, &str), Process>::new();
+///
+/// With `by_cmd`, processes running the same command are aggregated together even when they
+/// accessed different mounts.
+fn aggregate_processes(
+    processes: &TrackedProcesses,
+    system: &System,
+    by_cmd: bool,
+) -> Vec<Process> {
+    // Threads of the same process are aggregated by TGID. When it isn't known (the process already
+    // exited when we started tracking it, or we aren't on Linux), fall back to aggregating by cmd.
+    let mut aggregated_processes = BTreeMap::<AggregationKey, Process>::new();
 
     for (_pid, process) in processes.iter() {
-        match aggregated_processes.get_mut(&(&process.mount_name, &process.cmd)) {
+        match aggregated_processes.get_mut(&AggregationKey::new(process, by_cmd)) {
             Some(agg_proc) => {
                 // We aggregate access counts, but we don't change fetch counts
                 // (this matches behavior in original python implementation)
                 agg_proc.access_counts.add(&process.access_counts);
+                agg_proc
+                    .previous_access_counts
+                    .add(&process.previous_access_counts);
+                if agg_proc.mount_name != process.mount_name {
+                    agg_proc.mount_name = ALL_MOUNTS.to_string();
+                }
 
                 // Figure out what the most relevant process id is
                 if process.is_running(system)
@@ -250,16 +1049,210 @@ fn aggregate_processes(processes: &TrackedProcesses, system: &System) -> Vec<Pro
                 }
             }
             None => {
-                aggregated_processes.insert((&process.mount_name, &process.cmd), process.clone());
+                aggregated_processes.insert(AggregationKey::new(process, by_cmd), process.clone());
             }
         }
     }
 
     let mut sorted_processes = aggregated_processes.into_values().collect::<Vec<Process>>();
-    sorted_processes.sort_by(|a, b| b.last_access_time.cmp(&a.last_access_time));
+    // Most recently active first, processes active at the same time are always shown in the same
+    // order rather than the order they happened to be aggregated in.
+    sorted_processes.sort_by(|a, b| {
+        b.last_access_time
+            .cmp(&a.last_access_time)
+            .then_with(|| (&a.mount_name, &a.cmd, a.pid).cmp(&(&b.mount_name, &b.cmd, b.pid)))
+    });
     sorted_processes
 }
 
+/// Format a count with thousands separators, e.g. `1234567` becomes `1,234,567`.
+fn format_with_separators(count: i64) -> String {
+    let digits = count.unsigned_abs().to_string();
+    let mut formatted = String::with_capacity(digits.len() + digits.len() / 3 + 1);
+    if count < 0 {
+        formatted.push('-');
+    }
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i) % 3 == 0 {
+            formatted.push(',');
+        }
+        formatted.push(digit);
+    }
+    formatted
+}
+
+/// Format a count with a metric suffix, e.g. `1234` becomes `1.2K`.
+fn format_with_suffix(count: i64) -> String {
+    let abs = count.unsigned_abs();
+    if abs >= 1_000_000_000 {
+        format!("{:.1}G", count as f64 / 1_000_000_000.0)
+    } else if abs >= 1_000_000 {
+        format!("{:.1}M", count as f64 / 1_000_000.0)
+    } else if abs >= 1_000 {
+        format!("{:.1}K", count as f64 / 1_000.0)
+    } else {
+        count.to_string()
+    }
+}
+
+/// Escape a Prometheus label value as described in the text exposition format.
+fn escape_prometheus_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Write the aggregated processes to `path` in the Prometheus textfile format.
+///
+/// Series are labeled by mount and cmd only, so processes that share both are summed into a
+/// single series: a textfile collector rejects files with duplicate series.
+///
+/// The metrics are first written to a temporary file in the same directory which is then renamed
+/// into place, so that a textfile collector never observes a partially written file.
+fn write_prometheus_textfile(path: &Path, processes: &[Process]) -> Result<()> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| anyhow!("Prometheus output path is missing a file name"))?;
+    let mut tmp_name = OsString::from(".");
+    tmp_name.push(file_name);
+    tmp_name.push(".tmp");
+    let tmp_path = path.with_file_name(tmp_name);
+
+    let mut file = BufWriter::new(File::create(&tmp_path)?);
+    for (name, help, get_value) in PROMETHEUS_METRICS {
+        writeln!(file, "# HELP {} {}", name, help)?;
+        writeln!(file, "# TYPE {} counter", name)?;
+        let mut series = BTreeMap::<(&str, &str), i64>::new();
+        for process in processes {
+            let value = series
+                .entry((process.mount_name.as_str(), process.cmd.as_str()))
+                .or_default();
+            *value = value.saturating_add(get_value(process));
+        }
+        for ((mount_name, cmd), value) in series {
+            writeln!(
+                file,
+                "{}{{mount=\"{}\",cmd=\"{}\"}} {}",
+                name,
+                escape_prometheus_label(mount_name),
+                escape_prometheus_label(cmd),
+                value
+            )?;
+        }
+    }
+    file.into_inner()?.sync_all()?;
+
+    std::fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+/// Records the aggregated processes of every refresh into a SQLite database, for `--record`.
+struct Recorder {
+    connection: Connection,
+}
+
+impl Recorder {
+    fn open(path: &Path) -> Result<Self> {
+        let connection = Connection::open(path)?;
+        connection.execute(
+            "CREATE TABLE IF NOT EXISTS samples (
+                timestamp REAL NOT NULL,
+                pid INTEGER NOT NULL,
+                mount TEXT NOT NULL,
+                cmd TEXT NOT NULL,
+                reads INTEGER NOT NULL,
+                writes INTEGER NOT NULL,
+                total INTEGER NOT NULL,
+                fetches INTEGER NOT NULL,
+                memory_cache_imports INTEGER NOT NULL,
+                disk_cache_imports INTEGER NOT NULL,
+                backing_store_imports INTEGER NOT NULL,
+                duration_ns INTEGER NOT NULL
+            )",
+            [],
+        )?;
+        Ok(Self { connection })
+    }
+
+    /// Append one row per process, all sharing the same `timestamp`.
+    ///
+    /// The rows of a refresh are inserted in a single transaction, which is much faster than
+    /// letting SQLite commit every row on its own.
+    fn record(&mut self, timestamp: SystemTime, processes: &[Process]) -> Result<()> {
+        let timestamp = timestamp.duration_since(UNIX_EPOCH)?.as_secs_f64();
+        let transaction = self.connection.transaction()?;
+        {
+            let mut insert = transaction.prepare_cached(
+                "INSERT INTO samples VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+            )?;
+            for process in processes {
+                let counts = &process.access_counts;
+                insert.execute(params![
+                    timestamp,
+                    process.pid,
+                    process.mount_name,
+                    process.cmd,
+                    counts.fsChannelReads,
+                    counts.fsChannelWrites,
+                    counts.fsChannelTotal,
+                    process.fetch_counts,
+                    counts.fsChannelMemoryCacheImports,
+                    counts.fsChannelDiskCacheImports,
+                    counts.fsChannelBackingStoreImports,
+                    counts.fsChannelDurationNs,
+                ])?;
+            }
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+}
+
+/// Sort the aggregated processes so that the processes of each mount are next to each other.
+///
+/// Mounts are ordered by their most recently active process, and the processes of a mount keep
+/// their relative order.
+fn group_by_mount(processes: &mut [Process]) {
+    let mut mount_ranks = BTreeMap::new();
+    for process in processes.iter() {
+        let rank = mount_ranks.len();
+        mount_ranks
+            .entry(process.mount_name.clone())
+            .or_insert(rank);
+    }
+    processes.sort_by_key(|process| mount_ranks[&process.mount_name]);
+}
+
+/// Split processes sorted by `group_by_mount` into one slice per mount.
+fn mount_groups(processes: &[Process]) -> Vec<&[Process]> {
+    let mut groups = Vec::new();
+    let mut start = 0;
+    for index in 1..=processes.len() {
+        if index == processes.len() || processes[index].mount_name != processes[start].mount_name {
+            groups.push(&processes[start..index]);
+            start = index;
+        }
+    }
+    groups
+}
+
+/// Sum the counts of all the passed in processes into a single `Process`.
+fn total_process(processes: &[Process]) -> Process {
+    let mut total = Process::new(0, None, String::new(), None, SystemTime::now());
+    for process in processes {
+        total.access_counts.add(&process.access_counts);
+        total.fetch_counts = total.fetch_counts.saturating_add(process.fetch_counts);
+        total
+            .previous_access_counts
+            .add(&process.previous_access_counts);
+        total.previous_fetch_counts = total
+            .previous_fetch_counts
+            .saturating_add(process.previous_fetch_counts);
+    }
+    total
+}
+
 struct ImportStat {
     count: i64,
     max_duration_us: i64,
@@ -382,6 +1375,8 @@ impl Drop for TerminalAttributes {
 struct Cursor {
     row: u16,
     terminal_rows: u16,
+    /// Whether the output goes to a terminal, otherwise no escape sequences are emitted.
+    is_tty: bool,
 }
 
 impl Cursor {
@@ -389,11 +1384,26 @@ impl Cursor {
         let (_, row) = cursor::position()?;
         let (_, terminal_rows) = terminal::size()?;
 
-        Ok(Self { row, terminal_rows })
+        Ok(Self {
+            row,
+            terminal_rows,
+            is_tty: true,
+        })
+    }
+
+    /// A cursor for output that isn't going to a terminal, eg: a file or a pipe.
+    fn plain() -> Self {
+        Self {
+            row: 0,
+            terminal_rows: 0,
+            is_tty: false,
+        }
     }
 
     fn new_line(&mut self, stdout: &mut Stdout) -> Result<(), std::io::Error> {
-        if self.row == self.terminal_rows {
+        if !self.is_tty {
+            queue!(stdout, style::Print("\n"))
+        } else if self.row == self.terminal_rows {
             queue!(stdout, terminal::ScrollUp(1), cursor::MoveToColumn(1))
         } else {
             self.row += 1;
@@ -401,7 +1411,24 @@ impl Cursor {
         }
     }
 
+    /// Move the cursor back to the top of the screen and clear everything below it, so that the
+    /// next render replaces the previous one instead of being appended after it.
+    fn clear_screen(&mut self, stdout: &mut Stdout) -> Result<(), std::io::Error> {
+        if !self.is_tty {
+            return Ok(());
+        }
+        self.row = 0;
+        queue!(
+            stdout,
+            cursor::MoveTo(0, 0),
+            terminal::Clear(terminal::ClearType::FromCursorDown)
+        )
+    }
+
     fn refresh_terminal_size(&mut self) -> Result<()> {
+        if !self.is_tty {
+            return Ok(());
+        }
         let (_, terminal_rows) = terminal::size()?;
         self.terminal_rows = terminal_rows;
 
@@ -418,63 +1445,132 @@ impl Cursor {
 #[async_trait]
 impl crate::Subcommand for MinitopCmd {
     async fn run(&self) -> Result<ExitCode> {
-        let client = EdenFsInstance::global().connect(None).await?;
+        let instance = EdenFsInstance::global();
+        let socket_path = match &self.socket {
+            Some(socket_path) => {
+                if !socket_path.exists() {
+                    return Err(anyhow!(
+                        "EdenFS socket {} does not exist",
+                        socket_path.display()
+                    ));
+                }
+                socket_path.clone()
+            }
+            None => instance.socket_path(),
+        };
+        let client = match instance.connect_to_socket(socket_path.clone(), None).await {
+            Ok(client) => client,
+            Err(e) => {
+                let e = anyhow::Error::from(e);
+                if !is_not_running_error(&e) {
+                    return Err(e);
+                }
+                event!(Level::DEBUG, ?e, "Error while connecting to EdenFS daemon");
+                eprintln!("EdenFS daemon is not running; start it with `edenfsctl start`");
+                return Ok(EDENFS_NOT_RUNNING_EXIT_CODE);
+            }
+        };
+        // The daemon's own resource usage is read from the process table like for its clients.
+        let daemon_pid = client.getDaemonInfo().await?.pid;
         let mut tracked_processes = TrackedProcesses::new();
+        let mut recorder = self.record.as_deref().map(Recorder::open).transpose()?;
 
         let mut system = System::new();
 
-        // Setup rendering
-        let mut attributes = TerminalAttributes::new()
-            .disable_line_wrap()?
-            .enter_raw_mode()?;
-        if self.interactive {
-            attributes = attributes.enter_alt_screen()?;
+        // Setup rendering. When the output is redirected to a file or a pipe, terminal control
+        // sequences would only corrupt it, so the table is printed as plain text instead.
+        let mut stdout = stdout();
+        let is_tty = stdout.is_tty();
+        let mut attributes = TerminalAttributes::new();
+        if is_tty {
+            attributes = attributes.disable_line_wrap()?.enter_raw_mode()?;
+            if self.interactive {
+                attributes = attributes.enter_alt_screen()?;
+            }
         }
         let _ = attributes; // silence warning
 
-        let mut stdout = stdout();
-        let mut cursor = Cursor::new()?;
+        let color_enabled = is_tty && !self.no_color;
+        let theme = self.theme.theme();
+        let mut cursor = if is_tty {
+            Cursor::new()?
+        } else {
+            Cursor::plain()
+        };
         let mut events = EventStream::new();
+        // Can be changed at runtime with `+` and `-`.
+        let mut refresh_rate = self.refresh_rate;
+        let delay = tokio::time::sleep(refresh_rate);
+        tokio::pin!(delay);
+        let mut paused = false;
+        // Set when the screen only needs to be redrawn, eg: after the terminal was resized.
+        let mut redraw_only = false;
+        let mut last_refresh: Option<Instant> = None;
+        let mut remaining_iterations = self.iterations;
+        let mut interval = self.refresh_rate;
+        let mut pending_imports = BTreeMap::new();
+        let mut live_imports = BTreeMap::new();
 
         loop {
-            if self.interactive {
-                queue!(stdout, terminal::Clear(terminal::ClearType::All))?;
-            }
-            client.flushStatsNow();
-            system.refresh_processes();
+            cursor.clear_screen(&mut stdout)?;
             cursor.refresh_terminal_size()?;
 
-            // Update pending imports summary stats
-            let (pending_imports, live_imports) = tokio::try_join!(
-                get_pending_import_counts(&client),
-                get_live_import_counts(&client)
-            )?;
-
-            // Update currently tracked processes (and add new ones if they haven't been tracked yet)
-            let counts = client
-                .getAccessCounts(self.refresh_rate.as_secs().try_into()?)
-                .await?;
+            // While paused, keep rendering the last snapshot without querying EdenFS.
+            let refresh = !paused && !redraw_only;
+            if refresh {
+                client.flushStatsNow();
+                system.refresh_processes();
 
-            for (mount, accesses) in &counts.accessesByMount {
-                let mount_name = get_mount_name(mount)?;
+                // Update pending imports summary stats
+                (pending_imports, live_imports) = tokio::try_join!(
+                    get_pending_import_counts(&client),
+                    get_live_import_counts(&client)
+                )?;
 
-                for (pid, access_counts) in &accesses.accessCountsByPid {
-                    tracked_processes
-                        .entry(*pid)
-                        .or_insert_with(|| Process::new(*pid, mount_name.clone()))
-                        .set_cmd(counts.get_cmd_for_pid(*pid)?)
-                        .increment_access_counts(access_counts);
+                for process in tracked_processes.values_mut() {
+                    process.start_interval();
                 }
 
-                for (pid, fetch_counts) in &accesses.fetchCountsByPid {
-                    tracked_processes
-                        .entry(*pid)
-                        .or_insert_with(|| Process::new(*pid, mount_name.clone()))
-                        .set_cmd(counts.get_cmd_for_pid(*pid)?)
-                        .set_fetch_counts(*fetch_counts);
+                let counts = client
+                    .getAccessCounts(access_counts_duration(refresh_rate))
+                    .await?;
+
+                // `getAccessCounts` may be slow, so measure the actual time between refreshes
+                // rather than relying on the refresh rate.
+                let now = Instant::now();
+                if let Some(last_refresh) = last_refresh {
+                    interval = now.duration_since(last_refresh);
                 }
+                last_refresh = Some(now);
+
+                update_processes(
+                    &mut tracked_processes,
+                    &counts,
+                    &system,
+                    get_tgid,
+                    SystemTime::now(),
+                )?;
+            }
+
+            if paused {
+                queue!(stdout, style::Print("[PAUSED] press space to resume"))?;
+                cursor.new_line(&mut stdout)?;
             }
 
+            queue!(
+                stdout,
+                style::Print(format!("socket: {}", socket_path.display()))
+            )?;
+            cursor.new_line(&mut stdout)?;
+            queue!(
+                stdout,
+                style::Print(self.render_summary(&tracked_processes, refresh_rate, interval))
+            )?;
+            if let Some(daemon_usage) = render_daemon_usage(&system, daemon_pid) {
+                queue!(stdout, style::Print(format!("  {}", daemon_usage)))?;
+            }
+            cursor.new_line(&mut stdout)?;
+
             // Render pending trees/blobs
             for import_type in IMPORT_OBJECT_TYPES {
                 let pending_counts = pending_imports
@@ -502,41 +1598,118 @@ impl crate::Subcommand for MinitopCmd {
                 cursor.new_line(&mut stdout)?;
             }
 
+            let delta_processes;
+            let displayed_processes = if self.delta {
+                delta_processes = tracked_processes
+                    .iter()
+                    .map(|(pid, process)| (*pid, process.delta()))
+                    .collect::<TrackedProcesses>();
+                &delta_processes
+            } else {
+                &tracked_processes
+            };
+            let mut aggregated_processes =
+                aggregate_processes(displayed_processes, &system, self.by_cmd);
+            if let Some(path) = &self.prometheus {
+                // Counters must never decrease, so they are exported cumulatively even with
+                // `--delta`.
+                if self.delta {
+                    let cumulative_processes =
+                        aggregate_processes(&tracked_processes, &system, self.by_cmd);
+                    write_prometheus_textfile(path, &cumulative_processes)?;
+                } else {
+                    write_prometheus_textfile(path, &aggregated_processes)?;
+                }
+            }
+            // Only record fresh counts, not redraws of the same snapshot.
+            if let Some(recorder) = &mut recorder {
+                if refresh {
+                    recorder.record(SystemTime::now(), &aggregated_processes)?;
+                }
+            }
+
+            // Exported counts cover every process, only the table hides the idle ones.
+            aggregated_processes
+                .retain(|process| process.access_counts.fsChannelTotal >= self.min_fuse_count);
+
             // Render aggregated processes
+            let total = total_process(&aggregated_processes);
+            let context = RenderContext {
+                interval,
+                total_fetch_counts: total.fetch_counts,
+            };
+            if self.subtotals {
+                group_by_mount(&mut aggregated_processes);
+            }
+            let groups = if self.subtotals {
+                mount_groups(&aggregated_processes)
+            } else {
+                vec![&aggregated_processes[..]]
+            };
+            let mut rows = Vec::new();
+            for group in groups {
+                for aggregated_process in group {
+                    rows.push(Row {
+                        cells: self
+                            .columns()
+                            .iter()
+                            .map(|column| self.render_column(*column, aggregated_process, &context))
+                            .collect::<Result<Vec<String>>>()?,
+                        style: if !aggregated_process.is_running(&system) {
+                            RowStyle::Dead
+                        } else {
+                            match self.highlight_color(&theme, aggregated_process) {
+                                Some(color) => RowStyle::Highlighted(color),
+                                None => RowStyle::Normal,
+                            }
+                        },
+                    });
+                }
+                if self.subtotals {
+                    let mut subtotal = total_process(group);
+                    subtotal.mount_name = group[0].mount_name.clone();
+                    rows.push(Row {
+                        cells: self
+                            .columns()
+                            .iter()
+                            .map(|column| self.render_subtotal_column(*column, &subtotal, &context))
+                            .collect::<Result<Vec<String>>>()?,
+                        style: RowStyle::Subtotal,
+                    });
+                }
+            }
+            rows.push(Row {
+                cells: self
+                    .columns()
+                    .iter()
+                    .map(|column| self.render_total_column(*column, &total, &context))
+                    .collect::<Result<Vec<String>>>()?,
+                style: RowStyle::Total,
+            });
+
+            let header = self.header();
+            if is_tty && !self.full_cmd && !self.wide {
+                if let Some(cmd_index) = self.columns().iter().position(|c| *c == Column::Cmd) {
+                    let (terminal_width, _) = terminal::size()?;
+                    truncate_column(
+                        header.as_deref(),
+                        &mut rows,
+                        cmd_index,
+                        terminal_width.into(),
+                    );
+                }
+            }
+
             let mut table = Table::new();
-            table.set_header(COLUMN_TITLES);
-            table.load_preset(UTF8_BORDERS_ONLY);
-            for aggregated_process in aggregate_processes(&tracked_processes, &system) {
-                table.add_row(vec![
-                    aggregated_process.pid.to_string(),
-                    aggregated_process.mount_name.clone(),
-                    aggregated_process.access_counts.fsChannelReads.to_string(),
-                    aggregated_process.access_counts.fsChannelWrites.to_string(),
-                    aggregated_process.access_counts.fsChannelTotal.to_string(),
-                    aggregated_process.fetch_counts.to_string(),
-                    aggregated_process
-                        .access_counts
-                        .fsChannelMemoryCacheImports
-                        .to_string(),
-                    aggregated_process
-                        .access_counts
-                        .fsChannelDiskCacheImports
-                        .to_string(),
-                    aggregated_process
-                        .access_counts
-                        .fsChannelBackingStoreImports
-                        .to_string(),
-                    HumanTime::from(Duration::from_nanos(
-                        aggregated_process
-                            .access_counts
-                            .fsChannelDurationNs
-                            .try_into()?,
-                    ))
-                    .simple_human_time(TimeUnit::Nanoseconds),
-                    HumanTime::from(aggregated_process.last_access_time.elapsed())
-                        .simple_human_time(TimeUnit::Seconds),
-                    aggregated_process.cmd,
-                ]);
+            if let Some(header) = header {
+                table.set_header(header);
+            }
+            table.load_preset(theme.preset);
+            if self.wide {
+                table.set_content_arrangement(ContentArrangement::Disabled);
+            }
+            for row in rows {
+                table.add_row(row.into_cells(color_enabled));
             }
 
             for line in table.lines() {
@@ -547,21 +1720,67 @@ impl crate::Subcommand for MinitopCmd {
             cursor.new_line(&mut stdout)?;
             stdout.flush()?;
 
+            if refresh {
+                if let Some(remaining) = &mut remaining_iterations {
+                    *remaining = remaining.saturating_sub(1);
+                    if *remaining == 0 {
+                        // Returning drops `attributes`, which restores the terminal state.
+                        return Ok(0);
+                    }
+                }
+            }
+
+            if redraw_only {
+                redraw_only = false;
+            } else {
+                delay
+                    .as_mut()
+                    .reset(tokio::time::Instant::now() + refresh_rate);
+            }
+            if !is_tty {
+                // Without a terminal there are no key presses to wait for.
+                delay.as_mut().await;
+                continue;
+            }
             loop {
-                let delay = tokio::time::sleep(self.refresh_rate);
                 let event = events.next().fuse();
 
                 tokio::select! {
-                    _ = delay => { break }
+                    _ = &mut delay => { break }
                     maybe_event = event => {
                         match maybe_event {
                             Some(event) => {
                                 let event = event?;
 
                                 let q = Event::Key(KeyEvent::new(KeyCode::Char('q'), KeyModifiers::NONE));
+                                let esc = Event::Key(KeyEvent::new(KeyCode::Esc, KeyModifiers::NONE));
                                 let ctrlc = Event::Key(KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL));
-                                if event == q || event == ctrlc {
+                                let space = Event::Key(KeyEvent::new(KeyCode::Char(' '), KeyModifiers::NONE));
+                                // Returning drops `attributes`, which restores the terminal state.
+                                if event == q || event == esc || event == ctrlc {
                                     return Ok(0);
+                                } else if event == space {
+                                    paused = !paused;
+                                    break;
+                                } else if let Event::Key(KeyEvent {
+                                    code: KeyCode::Char(key @ ('+' | '-')),
+                                    ..
+                                }) = event
+                                {
+                                    refresh_rate = if key == '+' {
+                                        faster_refresh_rate(refresh_rate)
+                                    } else {
+                                        slower_refresh_rate(refresh_rate)
+                                    };
+                                    delay.as_mut().reset(tokio::time::Instant::now() + refresh_rate);
+                                    // Show the new refresh rate right away.
+                                    redraw_only = true;
+                                    break;
+                                } else if let Event::Resize(_, _) = event {
+                                    // Re-render right away so the table is laid out for the new
+                                    // terminal size, without refreshing the counts early.
+                                    redraw_only = true;
+                                    break;
                                 }
                             },
                             None => break,
@@ -572,3 +1791,500 @@ impl crate::Subcommand for MinitopCmd {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use thrift_types::edenfs::types::MountAccesses;
+
+    use super::*;
+
+    #[test]
+    fn test_get_cmd_for_pid() -> Result<()> {
+        let mut counts = GetAccessCountsResult::default();
+        counts
+            .cmdsByPid
+            .insert(1, b"/usr/bin/cat\0some file\0".to_vec());
+
+        assert_eq!(counts.get_cmd_for_pid(1)?, "cat \"some file\"");
+        assert_eq!(counts.get_cmd_for_pid(2)?, UNKNOWN_COMMAND);
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_cmd_for_pid_non_utf8() -> Result<()> {
+        let mut counts = GetAccessCountsResult::default();
+        counts
+            .cmdsByPid
+            .insert(1, b"/usr/bin/cat\0caf\xe9\0".to_vec());
+
+        assert_eq!(counts.get_cmd_for_pid(1)?, "cat caf\u{FFFD}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_update_processes_nfs_mount() -> Result<()> {
+        // NFS mounts report their accesses through the same fields as FUSE mounts.
+        let mut accesses = MountAccesses::default();
+        accesses.accessCountsByPid.insert(
+            42,
+            AccessCounts {
+                fsChannelTotal: 3,
+                fsChannelReads: 2,
+                fsChannelWrites: 1,
+                ..Default::default()
+            },
+        );
+        let mut counts = GetAccessCountsResult::default();
+        counts
+            .accessesByMount
+            .insert(b"/Users/me/nfs-repo".to_vec(), accesses);
+
+        let mut tracked_processes = TrackedProcesses::new();
+        update_processes(
+            &mut tracked_processes,
+            &counts,
+            &System::new(),
+            own_tgid,
+            SystemTime::now(),
+        )?;
+
+        let process = &tracked_processes[&42];
+        assert_eq!(process.mount_name, "nfs-repo");
+        assert_eq!(process.access_counts.fsChannelReads, 2);
+        assert_eq!(process.access_counts.fsChannelWrites, 1);
+        assert_eq!(process.access_counts.fsChannelTotal, 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_record() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("minitop.sqlite");
+
+        let now = SystemTime::now();
+        let mut process = Process::new(42, None, "fbsource".to_string(), None, now);
+        process.set_cmd("cat".to_string());
+        process.increment_access_counts(
+            &AccessCounts {
+                fsChannelTotal: 3,
+                fsChannelReads: 2,
+                fsChannelWrites: 1,
+                ..Default::default()
+            },
+            now,
+        );
+
+        let mut recorder = Recorder::open(&path)?;
+        recorder.record(SystemTime::now(), &[process.clone()])?;
+        recorder.record(SystemTime::now(), &[process])?;
+        drop(recorder);
+
+        // Reopening an existing database keeps the previously recorded samples.
+        let recorder = Recorder::open(&path)?;
+        let (count, reads): (i64, i64) = recorder.connection.query_row(
+            "SELECT COUNT(*), SUM(reads) FROM samples WHERE mount = 'fbsource' AND cmd = 'cat'",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        assert_eq!(count, 2);
+        assert_eq!(reads, 4);
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_prometheus_textfile_same_cmd() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("minitop.prom");
+
+        // Two processes with different TGIDs, which aren't aggregated together, share a series.
+        let now = SystemTime::now();
+        let processes = [(10, 2), (20, 3)]
+            .into_iter()
+            .map(|(pid, reads)| {
+                let mut process = Process::new(pid, Some(pid), "fbsource".to_string(), None, now);
+                process.set_cmd("cat".to_string());
+                process.increment_access_counts(
+                    &AccessCounts {
+                        fsChannelReads: reads,
+                        ..Default::default()
+                    },
+                    now,
+                );
+                process
+            })
+            .collect::<Vec<Process>>();
+
+        write_prometheus_textfile(&path, &processes)?;
+        let content = std::fs::read_to_string(&path)?;
+        let reads = content
+            .lines()
+            .filter(|line| line.starts_with("eden_fuse_reads_total{"))
+            .collect::<Vec<&str>>();
+        assert_eq!(
+            reads,
+            &["eden_fuse_reads_total{mount=\"fbsource\",cmd=\"cat\"} 5"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_render_summary() -> Result<()> {
+        let cmd = MinitopCmd::try_parse_from(["minitop"])?;
+        let counts = AccessCounts {
+            fsChannelTotal: 600,
+            ..Default::default()
+        };
+
+        let mut tracked_processes = TrackedProcesses::new();
+        for (pid, mount_name) in [(1, "fbsource"), (2, "fbsource"), (3, "www")] {
+            let now = SystemTime::now();
+            let mut process = Process::new(pid, None, mount_name.to_string(), None, now);
+            process.increment_access_counts(&counts, now);
+            process.start_interval();
+            process.increment_access_counts(&counts, now);
+            tracked_processes.insert(pid, process);
+        }
+
+        assert_eq!(
+            cmd.render_summary(
+                &tracked_processes,
+                Duration::from_secs(1),
+                Duration::from_millis(1500)
+            ),
+            "mounts: 2  processes: 3  fs ops this interval: 1,800  refresh: 1.0s (actual 1.5s)"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_imports_detail() -> Result<()> {
+        let cmd = MinitopCmd::try_parse_from(["minitop"])?;
+        assert_eq!(cmd.column_title(Column::Imports), "IMPORTS");
+        assert_eq!(cmd.format_import_count(1234), "1.2K");
+
+        let cmd = MinitopCmd::try_parse_from(["minitop", "--imports-detail"])?;
+        assert_eq!(cmd.column_title(Column::Memory), "MEM IMP");
+        assert_eq!(cmd.column_title(Column::Disk), "DISK IMP");
+        assert_eq!(cmd.column_title(Column::Imports), "BACKING IMP");
+        assert_eq!(cmd.column_title(Column::Fetches), "FETCHES");
+        assert_eq!(cmd.format_import_count(1234), "1,234");
+        Ok(())
+    }
+
+    /// Treat every pid as a separate process, rather than looking it up on the machine running
+    /// the tests.
+    fn own_tgid(pid: pid_t) -> Option<pid_t> {
+        Some(pid)
+    }
+
+    /// Build a `getAccessCounts` result where each of `accesses` did a single read.
+    fn access_counts_result(accesses: &[(&str, pid_t, &str)]) -> GetAccessCountsResult {
+        let mut counts = GetAccessCountsResult::default();
+        for (mount, pid, cmd) in accesses {
+            counts
+                .accessesByMount
+                .entry(format!("/data/users/me/{}", mount).into_bytes())
+                .or_default()
+                .accessCountsByPid
+                .insert(
+                    *pid,
+                    AccessCounts {
+                        fsChannelTotal: 1,
+                        fsChannelReads: 1,
+                        ..Default::default()
+                    },
+                );
+            counts
+                .cmdsByPid
+                .insert(*pid, format!("{}\0", cmd).into_bytes());
+        }
+        counts
+    }
+
+    #[test]
+    fn test_aggregate_processes_order() -> Result<()> {
+        let system = System::new();
+        let start = UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let mut tracked_processes = TrackedProcesses::new();
+
+        update_processes(
+            &mut tracked_processes,
+            &access_counts_result(&[
+                ("www", 30, "cat"),
+                ("fbsource", 20, "ls"),
+                ("fbsource", 10, "cat"),
+            ]),
+            &system,
+            own_tgid,
+            start,
+        )?;
+        update_processes(
+            &mut tracked_processes,
+            &access_counts_result(&[("www", 30, "cat")]),
+            &system,
+            own_tgid,
+            start + Duration::from_secs(1),
+        )?;
+
+        let order = |tracked_processes: &TrackedProcesses| {
+            aggregate_processes(tracked_processes, &system, false)
+                .iter()
+                .map(|process| process.pid)
+                .collect::<Vec<pid_t>>()
+        };
+        // The most recently active process comes first, ties are ordered by mount, cmd and pid.
+        assert_eq!(order(&tracked_processes), &[30, 10, 20]);
+
+        update_processes(
+            &mut tracked_processes,
+            &access_counts_result(&[("fbsource", 20, "ls"), ("fbsource", 10, "cat")]),
+            &system,
+            own_tgid,
+            start + Duration::from_secs(2),
+        )?;
+        assert_eq!(order(&tracked_processes), &[10, 20, 30]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_highlight_color() -> Result<()> {
+        let cmd = MinitopCmd::try_parse_from(["minitop", "--highlight-threshold", "100"])?;
+        let mut process = Process::new(1, None, "fbsource".to_string(), None, SystemTime::now());
+
+        let theme = ThemeName::Default.theme();
+        process.fetch_counts = 10;
+        assert_eq!(cmd.highlight_color(&theme, &process), None);
+        process.fetch_counts = 60;
+        assert_eq!(cmd.highlight_color(&theme, &process), Some(Color::Yellow));
+        process.fetch_counts = 200;
+        assert_eq!(cmd.highlight_color(&theme, &process), Some(Color::Red));
+
+        assert_eq!(
+            cmd.highlight_color(&ThemeName::HighContrast.theme(), &process),
+            Some(Color::Magenta)
+        );
+        assert_eq!(
+            cmd.highlight_color(&ThemeName::Mono.theme(), &process),
+            None
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_aggregate_processes_by_cmd() -> Result<()> {
+        let system = System::new();
+        let mut tracked_processes = TrackedProcesses::new();
+        update_processes(
+            &mut tracked_processes,
+            &access_counts_result(&[
+                ("fbsource", 10, "cat"),
+                ("www", 20, "cat"),
+                ("www", 30, "ls"),
+            ]),
+            &system,
+            own_tgid,
+            SystemTime::now(),
+        )?;
+
+        let aggregated = aggregate_processes(&tracked_processes, &system, true)
+            .into_iter()
+            .map(|process| {
+                (
+                    process.mount_name,
+                    process.cmd,
+                    process.access_counts.fsChannelReads,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            aggregated,
+            &[
+                (ALL_MOUNTS.to_string(), "cat".to_string(), 2),
+                ("www".to_string(), "ls".to_string(), 1),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_aggregate_processes_by_tgid() -> Result<()> {
+        let system = System::new();
+        let mut tracked_processes = TrackedProcesses::new();
+        // Threads 10 and 11 belong to process 10, process 20 runs the same cmd.
+        update_processes(
+            &mut tracked_processes,
+            &access_counts_result(&[
+                ("fbsource", 10, "cat"),
+                ("fbsource", 11, "cat"),
+                ("fbsource", 20, "cat"),
+            ]),
+            &system,
+            |pid| Some(if pid == 11 { 10 } else { pid }),
+            SystemTime::now(),
+        )?;
+
+        let aggregated = aggregate_processes(&tracked_processes, &system, false)
+            .into_iter()
+            .map(|process| (process.tgid, process.access_counts.fsChannelReads))
+            .collect::<Vec<_>>();
+        assert_eq!(aggregated, &[(Some(10), 2), (Some(20), 1)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_access_counts_saturate() {
+        let mut counts = AccessCounts {
+            fsChannelTotal: i64::MAX - 1,
+            fsChannelReads: i64::MAX,
+            fsChannelDurationNs: i64::MIN + 1,
+            ..Default::default()
+        };
+        counts.add(&AccessCounts {
+            fsChannelTotal: 10,
+            fsChannelReads: 1,
+            fsChannelWrites: 1,
+            ..Default::default()
+        });
+        assert_eq!(counts.fsChannelTotal, i64::MAX);
+        assert_eq!(counts.fsChannelReads, i64::MAX);
+        assert_eq!(counts.fsChannelWrites, 1);
+
+        counts.sub(&AccessCounts {
+            fsChannelDurationNs: 10,
+            ..Default::default()
+        });
+        assert_eq!(counts.fsChannelDurationNs, i64::MIN);
+        assert_eq!(counts.fsChannelTotal, i64::MAX);
+    }
+
+    #[test]
+    fn test_group_by_mount() {
+        let now = SystemTime::now();
+        let mut processes = [(1, "www"), (2, "fbsource"), (3, "www"), (4, "configerator")]
+            .into_iter()
+            .map(|(pid, mount_name)| Process::new(pid, None, mount_name.to_string(), None, now))
+            .collect::<Vec<Process>>();
+
+        group_by_mount(&mut processes);
+        let groups = mount_groups(&processes)
+            .into_iter()
+            .map(|group| group.iter().map(|process| process.pid).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        assert_eq!(groups, vec![vec![1, 3], vec![2], vec![4]]);
+    }
+
+    #[test]
+    fn test_no_header() -> Result<()> {
+        let cmd = MinitopCmd::try_parse_from(["minitop", "--columns", "pid,cmd"])?;
+        assert_eq!(cmd.header(), Some(vec!["PID", "CMD"]));
+
+        let cmd = MinitopCmd::try_parse_from(["minitop", "--columns", "pid,cmd", "--no-header"])?;
+        assert_eq!(cmd.header(), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_columns() -> Result<()> {
+        let cmd = MinitopCmd::try_parse_from(["minitop", "--columns", "pid,mount,fuse-count,cmd"])?;
+        assert_eq!(
+            cmd.columns(),
+            &[Column::Pid, Column::Mount, Column::FuseCount, Column::Cmd]
+        );
+
+        let cmd = MinitopCmd::try_parse_from(["minitop"])?;
+        assert_eq!(cmd.columns(), DEFAULT_COLUMNS);
+
+        assert!(MinitopCmd::try_parse_from(["minitop", "--columns", "pid,bogus"]).is_err());
+        assert!(MinitopCmd::try_parse_from(["minitop", "--wide", "--columns", "pid"]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_truncate_with_ellipsis() {
+        assert_eq!(truncate_with_ellipsis("cat foo", 10), "cat foo");
+        assert_eq!(truncate_with_ellipsis("cat foo", 7), "cat foo");
+        assert_eq!(truncate_with_ellipsis("cat foo", 6), "cat f\u{2026}");
+        assert_eq!(truncate_with_ellipsis("cat foo", 1), "\u{2026}");
+        assert_eq!(truncate_with_ellipsis("cat foo", 0), "");
+        // Wide characters take two columns each.
+        assert_eq!(
+            truncate_with_ellipsis("\u{4F60}\u{597D}", 3),
+            "\u{4F60}\u{2026}"
+        );
+    }
+
+    #[test]
+    fn test_is_not_running_error() {
+        let io_error = |kind| anyhow::Error::from(std::io::Error::from(kind));
+        assert!(is_not_running_error(&io_error(
+            std::io::ErrorKind::ConnectionRefused
+        )));
+        assert!(is_not_running_error(
+            &io_error(std::io::ErrorKind::NotFound).context("Failed to connect")
+        ));
+        assert!(!is_not_running_error(&io_error(
+            std::io::ErrorKind::PermissionDenied
+        )));
+        assert!(!is_not_running_error(&anyhow!("Timed out")));
+    }
+
+    #[test]
+    fn test_parse_refresh_rate() {
+        assert_eq!(parse_refresh_rate("1"), Ok(Duration::from_secs(1)));
+        assert_eq!(parse_refresh_rate("10"), Ok(Duration::from_secs(10)));
+        assert!(parse_refresh_rate("0").is_err());
+        assert!(parse_refresh_rate("-1").is_err());
+        assert!(parse_refresh_rate("abc").is_err());
+    }
+
+    #[test]
+    fn test_change_refresh_rate() {
+        assert_eq!(
+            faster_refresh_rate(Duration::from_secs(4)),
+            Duration::from_secs(2)
+        );
+        // Rates stay whole seconds.
+        assert_eq!(
+            faster_refresh_rate(Duration::from_secs(3)),
+            Duration::from_secs(1)
+        );
+        assert_eq!(
+            faster_refresh_rate(Duration::from_secs(1)),
+            MIN_REFRESH_RATE
+        );
+        assert_eq!(
+            slower_refresh_rate(Duration::from_secs(1)),
+            Duration::from_secs(2)
+        );
+        assert_eq!(
+            slower_refresh_rate(Duration::from_secs(45)),
+            MAX_REFRESH_RATE
+        );
+    }
+
+    #[test]
+    fn test_access_counts_duration() {
+        assert_eq!(access_counts_duration(Duration::from_millis(100)), 1);
+        assert_eq!(access_counts_duration(Duration::from_secs(1)), 1);
+        assert_eq!(access_counts_duration(Duration::from_millis(1500)), 2);
+        assert_eq!(access_counts_duration(Duration::from_secs(60)), 60);
+    }
+
+    #[test]
+    fn test_format_with_separators() {
+        assert_eq!(format_with_separators(0), "0");
+        assert_eq!(format_with_separators(999), "999");
+        assert_eq!(format_with_separators(1000), "1,000");
+        assert_eq!(format_with_separators(1234567), "1,234,567");
+        assert_eq!(format_with_separators(-1234567), "-1,234,567");
+    }
+
+    #[test]
+    fn test_format_with_suffix() {
+        assert_eq!(format_with_suffix(0), "0");
+        assert_eq!(format_with_suffix(999), "999");
+        assert_eq!(format_with_suffix(1234), "1.2K");
+        assert_eq!(format_with_suffix(3_400_000), "3.4M");
+        assert_eq!(format_with_suffix(5_600_000_000), "5.6G");
+    }
+}
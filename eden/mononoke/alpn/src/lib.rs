@@ -11,9 +11,20 @@ use anyhow::Error;
 pub const H2_ALPN: &str = "h2";
 pub const HGCLI_ALPN: &str = "hgcli";
 
+/// The protocols that Mononoke knows how to serve.
+pub const KNOWN_ALPNS: &[&str] = &[H2_ALPN, HGCLI_ALPN];
+
 pub fn alpn_select<'a>(protos: &'a [u8], desired: &str) -> Result<Option<&'a [u8]>, Error> {
+    alpn_select_any(protos, &[desired])
+}
+
+/// Select the first protocol offered by the client that is also in `supported`, so that the
+/// client's order of preference is respected.
+pub fn alpn_select_any<'a>(
+    protos: &'a [u8],
+    supported: &[&str],
+) -> Result<Option<&'a [u8]>, Error> {
     let mut pos = 0;
-    let desired = desired.as_bytes();
 
     loop {
         let size = match protos.get(pos) {
@@ -29,7 +40,7 @@ pub fn alpn_select<'a>(protos: &'a [u8], desired: &str) -> Result<Option<&'a [u8
 
         let slice = &protos[pos + 1..=end];
 
-        if slice == desired {
+        if supported.iter().any(|desired| slice == desired.as_bytes()) {
             return Ok(Some(slice));
         }
 
@@ -75,6 +86,31 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    pub fn test_alpn_select_any() -> Result<(), Error> {
+        let supported = [H2_ALPN, HGCLI_ALPN];
+
+        // The client's order of preference wins
+        assert_eq!(
+            alpn_select_any("\x02h2\x05hgcli".as_bytes(), &supported)?,
+            Some("h2".as_bytes())
+        );
+        assert_eq!(
+            alpn_select_any("\x05hgcli\x02h2".as_bytes(), &supported)?,
+            Some("hgcli".as_bytes())
+        );
+        assert_eq!(
+            alpn_select_any("\x08http/1.1\x05hgcli".as_bytes(), &supported)?,
+            Some("hgcli".as_bytes())
+        );
+        assert_eq!(
+            alpn_select_any("\x08http/1.1".as_bytes(), &supported)?,
+            None
+        );
+
+        Ok(())
+    }
+
     #[test]
     pub fn test_alpn_format() -> Result<(), Error> {
         assert_eq!(alpn_format("h2")?, "\x02h2".as_bytes());
@@ -20,6 +20,8 @@ use ascii::AsciiString;
 use quickcheck::Arbitrary;
 use quickcheck::Gen;
 use quickcheck_arbitrary_derive::Arbitrary;
+use serde::Serialize;
+use serde::Serializer;
 use sql::mysql;
 use sql::mysql_async::prelude::ConvIr;
 use sql::mysql_async::prelude::FromValue;
@@ -100,6 +102,15 @@ impl fmt::Display for BookmarkName {
     }
 }
 
+impl Serialize for BookmarkName {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(self.bookmark.as_str())
+    }
+}
+
 impl Arbitrary for BookmarkName {
     fn arbitrary(g: &mut Gen) -> Self {
         // NOTE: We use a specific large size here because our tests exercise DB Bookmarks, which
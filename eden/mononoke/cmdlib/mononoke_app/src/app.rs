@@ -304,6 +304,17 @@ impl MononokeApp {
         self.configs.storage_configs()
     }
 
+    /// Re-read the configs for this app, reloading the repos it opened with the new configs.
+    /// Returns the repo configs from before the reload.
+    pub async fn reload_configs(&self) -> Result<Arc<RepoConfigs>> {
+        self.configs.reload().await
+    }
+
+    /// Notify `update_receiver` every time the configs for this app are updated.
+    pub fn register_for_config_updates(&self, update_receiver: Arc<dyn ConfigUpdateReceiver>) {
+        self.configs.register_for_update(update_receiver);
+    }
+
     /// The logger for this app.
     pub fn logger(&self) -> &Logger {
         &self.env.logger
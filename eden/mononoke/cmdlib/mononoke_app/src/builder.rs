@@ -428,7 +428,8 @@ fn create_runtime(runtime_args: &RuntimeArgs) -> Result<Runtime> {
     let mut builder = tokio::runtime::Builder::new_multi_thread();
     builder.enable_all();
     builder.thread_name("tk");
-    if let Some(threads) = runtime_args.runtime_threads {
+    // Tokio panics on 0 worker threads, keep its default instead.
+    if let Some(threads) = runtime_args.runtime_threads.filter(|threads| *threads > 0) {
         builder.worker_threads(threads);
     }
     let runtime = builder.build()?;
@@ -53,11 +53,15 @@ impl ReadyFlagService {
     pub fn set_ready(&self) {
         self.ready.store(true, Ordering::Relaxed);
     }
+
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::Relaxed)
+    }
 }
 
 impl Fb303Service for ReadyFlagService {
     fn getStatus(&self) -> FbStatus {
-        if self.ready.load(Ordering::Relaxed) {
+        if self.is_ready() {
             FbStatus::Alive
         } else {
             FbStatus::Starting
@@ -75,6 +75,7 @@ use futures::stream::TryStreamExt;
 use futures_stats::TimedFutureExt;
 use futures_stats::TimedTryFutureExt;
 use mononoke_api_types::InnerRepo;
+use mononoke_configs::MononokeConfigs;
 use mononoke_types::BonsaiChangeset;
 use mononoke_types::ChangesetId;
 use mononoke_types::DateTime;
@@ -90,6 +91,7 @@ use stats::prelude::*;
 use time_ext::DurationExt;
 use topo_sort::sort_topological;
 use tunables::tunables;
+use wait_for_replication::MonitorsReloader;
 use wait_for_replication::WaitForReplication;
 
 mod commit_discovery;
@@ -668,10 +670,28 @@ async fn run_subcmd<'a>(
     let config_store = matches.config_store();
     let storage_config =
         args::get_config_by_name(config_store, matches, repo_name.clone())?.storage_config;
+    // Rebuild the replication lag monitors if the storage config of the repo changes while
+    // backfilling.
+    let configs = MononokeConfigs::new(
+        args::get_config_path(matches)?,
+        config_store,
+        tokio::runtime::Handle::current(),
+        logger.clone(),
+    )?;
+    let new_wait_for_replication = |config_name: &'static str| -> Result<WaitForReplication> {
+        let wait_for_replication =
+            WaitForReplication::new(fb, config_store, storage_config.clone(), config_name)?;
+        let repo_name = repo_name.clone();
+        configs.register_for_update(Arc::new(MonitorsReloader::new(
+            wait_for_replication.clone(),
+            logger.clone(),
+            move |repo_configs, _| Some(repo_configs.repos.get(&repo_name)?.storage_config.clone()),
+        )));
+        Ok(wait_for_replication)
+    };
     match matches.subcommand() {
         (SUBCOMMAND_BACKFILL_ALL, Some(sub_m)) => {
-            let wait_for_replication =
-                WaitForReplication::new(fb, config_store, storage_config, BACKFILLER_WAIT_CONFIG)?;
+            let wait_for_replication = new_wait_for_replication(BACKFILLER_WAIT_CONFIG)?;
             let repo: InnerRepo =
                 args::open_repo_by_name_unredacted(fb, logger, matches, repo_name).await?;
 
@@ -728,8 +748,7 @@ async fn run_subcmd<'a>(
             .await
         }
         (SUBCOMMAND_BACKFILL, Some(sub_m)) => {
-            let wait_for_replication =
-                WaitForReplication::new(fb, config_store, storage_config, BACKFILLER_WAIT_CONFIG)?;
+            let wait_for_replication = new_wait_for_replication(BACKFILLER_WAIT_CONFIG)?;
             let derived_data_type = sub_m
                 .value_of(ARG_DERIVED_DATA_TYPE)
                 .ok_or_else(|| format_err!("missing required argument: {}", ARG_DERIVED_DATA_TYPE))?
@@ -802,8 +821,7 @@ async fn run_subcmd<'a>(
             .await
         }
         (SUBCOMMAND_TAIL, Some(sub_m)) => {
-            let wait_for_replication =
-                WaitForReplication::new(fb, config_store, storage_config, TAILER_WAIT_CONFIG)?;
+            let wait_for_replication = new_wait_for_replication(TAILER_WAIT_CONFIG)?;
             let config_store = matches.config_store();
             let use_shared_leases = sub_m.is_present(ARG_USE_SHARED_LEASES);
             let stop_on_idle = sub_m.is_present(ARG_STOP_ON_IDLE);
@@ -1109,7 +1127,7 @@ async fn subcommand_backfill(
         );
         let (stats, chunk_size) = async {
             wait_for_replication
-                .wait_for_replication(ctx.logger())
+                .wait_for_replication(ctx.logger(), None)
                 .await?;
             let chunk = derived_utils
                 .pending(
@@ -1447,7 +1465,7 @@ async fn tail_batch_iteration<'a>(
                 async move {
                     if let Some(deriver) = &node.deriver {
                         wait_for_replication
-                            .wait_for_replication(ctx.logger())
+                            .wait_for_replication(ctx.logger(), None)
                             .await?;
                         let mut scuba =
                             create_derive_graph_scuba_sample(&ctx, &node.csids, deriver.name());
@@ -63,6 +63,7 @@ use sql_construct::SqlConstructFromDatabaseConfig;
 use sql_construct::SqlConstructFromShardedDatabaseConfig;
 use sql_ext::facebook::MysqlOptions;
 use sync_healer::SyncHealer;
+use wait_for_replication::MonitorsReloader;
 use wait_for_replication::WaitForReplication;
 use wal_healer::WalHealer;
 
@@ -102,6 +103,10 @@ struct MononokeBlobstoreHealerArgs {
     /// which shards to read from, useful for spawning multiple independent healers
     #[clap(long, default_value = "..")]
     shard_range: ShardRange,
+    /// If specified, record the replication lag every this many seconds, so that it is reported
+    /// even while the healer isn't waiting for replication
+    #[clap(long)]
+    replication_lag_sample_interval_secs: Option<u64>,
 }
 
 struct ShardRange {
@@ -162,8 +167,9 @@ async fn maybe_schedule_healer_for_storage(
     heal_min_age: ChronoDuration,
     config_store: &ConfigStore,
     shard_range: ShardRange,
+    wait_for_replication: WaitForReplication,
 ) -> Result<(), Error> {
-    let multiplex_healer = match storage_config.clone().blobstore {
+    let multiplex_healer = match storage_config.blobstore {
         BlobConfig::Multiplexed {
             blobstores,
             multiplex_id,
@@ -242,8 +248,6 @@ async fn maybe_schedule_healer_for_storage(
         s => bail!("Storage doesn't use Multiplexed blobstore, got {:?}", s),
     }?;
 
-    let wait_for_replication = WaitForReplication::new(fb, config_store, storage_config, "healer")?;
-
     schedule_healing(
         ctx,
         multiplex_healer,
@@ -379,7 +383,7 @@ async fn schedule_healing(
         }
 
         wait_for_replication
-            .wait_for_replication(ctx.logger())
+            .wait_for_replication(ctx.logger(), None)
             .await
             .context("While waiting for replication")?;
 
@@ -456,13 +460,23 @@ async fn async_main(app: MononokeApp) -> Result<(), Error> {
     let scuba = env.scuba_sample_builder.clone();
 
     let ctx = SessionContainer::new_with_defaults(app.fb).new_context(logger.clone(), scuba);
+    let wait_for_replication =
+        WaitForReplication::new(app.fb, config_store, storage_config.clone(), "healer")?;
+    app.register_for_config_updates(Arc::new(MonitorsReloader::new(
+        wait_for_replication.clone(),
+        logger.clone(),
+        move |_, storage_configs| storage_configs.storage.get(&storage_id).cloned(),
+    )));
+    let lag_sampler = args.replication_lag_sample_interval_secs.map(|interval| {
+        wait_for_replication.spawn_lag_sampler(logger.clone(), Duration::from_secs(interval))
+    });
     let buffered_params = BufferedParams {
         weight_limit: heal_max_bytes,
         buffer_size: heal_concurrency,
     };
     let shard_range = args.shard_range;
 
-    maybe_schedule_healer_for_storage(
+    let res = maybe_schedule_healer_for_storage(
         app.fb,
         &ctx,
         dry_run,
@@ -478,6 +492,11 @@ async fn async_main(app: MononokeApp) -> Result<(), Error> {
         healing_min_age,
         config_store,
         shard_range,
+        wait_for_replication,
     )
-    .await
+    .await;
+    if let Some(lag_sampler) = lag_sampler {
+        lag_sampler.abort();
+    }
+    res
 }
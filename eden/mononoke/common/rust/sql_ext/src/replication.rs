@@ -6,6 +6,8 @@
  */
 
 use std::fmt;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
 use std::time::Duration;
 
 use anyhow::Result;
@@ -72,6 +74,34 @@ impl ReplicaLagMonitor for NoReplicaLagMonitor {
     }
 }
 
+/// A monitor whose replication lag is set by hand, to simulate replication lag over databases
+/// that don't replicate, eg: SQLite databases in tests. The lag is zero until set.
+#[derive(Default)]
+pub struct ControlledReplicaLagMonitor {
+    lag_ms: AtomicU64,
+}
+
+impl ControlledReplicaLagMonitor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_lag(&self, lag: Duration) {
+        self.lag_ms.store(
+            lag.as_millis().try_into().unwrap_or(u64::MAX),
+            Ordering::Relaxed,
+        );
+    }
+}
+
+#[async_trait]
+impl ReplicaLagMonitor for ControlledReplicaLagMonitor {
+    async fn get_replica_lag(&self) -> Result<Vec<ReplicaLag>> {
+        let lag = Duration::from_millis(self.lag_ms.load(Ordering::Relaxed));
+        Ok(vec![ReplicaLag::new(lag, None)])
+    }
+}
+
 pub struct ReplicaLag {
     pub delay: Duration,
     pub details: Option<String>,
@@ -172,6 +202,18 @@ mod test {
         assert_matches!(lag, Ok(_expected));
     }
 
+    #[tokio::test]
+    async fn test_controlled_replica_lag_monitor() -> Result<()> {
+        let monitor = ControlledReplicaLagMonitor::new();
+        assert_eq!(monitor.get_max_replica_lag().await?.delay, Duration::ZERO);
+        monitor.set_lag(Duration::from_millis(1500));
+        assert_eq!(
+            monitor.get_max_replica_lag().await?.delay,
+            Duration::from_millis(1500)
+        );
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_max_lag() {
         let monitor = TestMonitor(5);
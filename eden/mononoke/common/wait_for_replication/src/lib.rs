@@ -5,221 +5,1478 @@
  * GNU General Public License version 2.
  */
 
-use std::ops::DerefMut;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
 use std::sync::Arc;
+use std::sync::RwLock;
 use std::time::Duration;
 use std::time::Instant;
 
+use anyhow::bail;
+use anyhow::Context;
 use anyhow::Result;
+use arc_swap::ArcSwap;
+use async_trait::async_trait;
 use cached_config::ConfigHandle;
 use cached_config::ConfigStore;
 use fbinit::FacebookInit;
-use futures::try_join;
+use futures::future::try_join_all;
+use metaconfig_parser::RepoConfigs;
+use metaconfig_parser::StorageConfigs;
 use metaconfig_types::BlobConfig;
 use metaconfig_types::BlobstoreId;
 use metaconfig_types::DatabaseConfig;
 use metaconfig_types::MultiplexedStoreType;
-#[cfg(fbcode_build)]
 use metaconfig_types::ShardableRemoteDatabaseConfig;
 use metaconfig_types::ShardedDatabaseConfig;
 use metaconfig_types::StorageConfig;
+use mononoke_configs::ConfigUpdateReceiver;
 use replication_lag_config::ReplicationLagBlobstoreConfig;
 use replication_lag_config::ReplicationLagTableConfig;
 use slog::info;
+use slog::warn;
 use slog::Logger;
-#[cfg(fbcode_build)]
 use sql_ext::facebook::MyAdmin;
+use sql_ext::replication::ControlledReplicaLagMonitor;
 use sql_ext::replication::NoReplicaLagMonitor;
+use sql_ext::replication::ReplicaLag;
 use sql_ext::replication::ReplicaLagMonitor;
-use sql_ext::replication::WaitForReplicationConfig;
+use stats::prelude::*;
+use thiserror::Error;
 use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+use tracing::info_span;
+use tracing::Instrument;
+use tunables::tunables;
+
+define_stats! {
+    prefix = "mononoke.wait_for_replication";
+    wait_ms: dynamic_timeseries("{}.wait_ms", (table: String); Rate, Sum),
+    wait_duration_ms: dynamic_histogram("{}.wait_duration_ms", (table: String); 500, 0, 60_000, Average, Sum, Count; P 50; P 90; P 99),
+    blocked: dynamic_timeseries("{}.blocked", (table: String); Rate, Sum),
+    stuck: dynamic_timeseries("{}.stuck", (table: String); Rate, Sum),
+    lag_ms: dynamic_singleton_counter("{}.lag_ms", (table: String)),
+}
+
+#[derive(Debug, Error)]
+pub enum WaitForReplicationError {
+    #[error("Gave up waiting for replication after {0:?}, still lagging: {1}")]
+    Timeout(Duration, String),
+    #[error("Gave up waiting for replication on {0} after {1} polls, lag is still {2:?}")]
+    TooManyPolls(&'static str, u32, Duration),
+}
 
 #[derive(Default)]
-struct State {
-    last_sync_queue_lag: Option<(Instant, Duration)>,
-    last_xdb_blobstore_lag: Option<(Instant, Duration)>,
+struct TableState {
+    last_lag: Option<(Instant, Duration)>,
+    /// While the lag is above the threshold: the last lag observed, and the interval until the
+    /// next poll. Used to back off when the lag isn't dropping.
+    lagging: Option<(Duration, Duration)>,
+    /// While the lag is above the threshold: the lowest lag observed, and the number of polls
+    /// since it last improved. Used to detect when replication is stuck rather than slow.
+    stuck: Option<(Duration, u32)>,
+}
+
+impl TableState {
+    /// Forget about the trend of the lag once done waiting on it.
+    fn stop_lagging(&mut self) {
+        self.lagging = None;
+        self.stuck = None;
+    }
+}
+
+/// The state of each table, by name.
+type State = HashMap<&'static str, TableState>;
+
+/// Name of the table of the blobstore sync queue, or WAL.
+pub const SYNC_QUEUE: &str = "sync queue";
+/// Name of the table of the XDB blobstore.
+pub const XDB_BLOBSTORE: &str = "XDB blobstore";
+
+/// Returns the current replication lag config of a table, `None` if it shouldn't be waited on.
+pub type TableConfigGetter = Arc<dyn Fn() -> Option<ReplicationLagTableConfig> + Send + Sync>;
+
+/// A table replicated through MySQL, whose replication lag can be waited on.
+#[derive(Clone)]
+pub struct ReplicatedTable {
+    name: &'static str,
+    monitor: Arc<dyn ReplicaLagMonitor>,
+    config: TableConfigGetter,
+}
+
+impl ReplicatedTable {
+    /// `name` must be unique among the tables of a `WaitForReplication`. `config` is queried
+    /// again on every poll, so that config changes apply to ongoing waits.
+    pub fn new(
+        name: &'static str,
+        monitor: Arc<dyn ReplicaLagMonitor>,
+        config: impl Fn() -> Option<ReplicationLagTableConfig> + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            name,
+            monitor,
+            config: Arc::new(config),
+        }
+    }
+}
+
+/// How to grow the interval between polls while the replication lag isn't dropping.
+#[derive(Clone, Copy, Debug)]
+pub struct Backoff {
+    /// Factor by which the interval grows after each poll where the lag didn't drop, must be at
+    /// least 1.
+    pub multiplier: f64,
+    /// The interval never grows past this, nor past the configured poll interval if larger.
+    pub max_interval: Duration,
+}
+
+impl Backoff {
+    fn validate(&self) -> Result<()> {
+        if !self.multiplier.is_finite() || self.multiplier < 1.0 {
+            bail!(
+                "Invalid backoff multiplier: {}, must be at least 1",
+                self.multiplier
+            );
+        }
+        Ok(())
+    }
+
+    /// The interval following `previous_interval`, capped at `max_interval`. The growth is
+    /// computed in floating point seconds so that it can't overflow a `Duration`.
+    fn next_interval(&self, previous_interval: Duration, max_interval: Duration) -> Duration {
+        let secs = previous_interval.as_secs_f64() * self.multiplier;
+        if secs < max_interval.as_secs_f64() {
+            Duration::from_secs_f64(secs)
+        } else {
+            max_interval
+        }
+    }
+}
+
+/// Called with the name of a table and its current lag every time it is polled while waiting for
+/// its replication lag to drop.
+pub type PollObserver = Arc<dyn Fn(&str, Duration) + Send + Sync>;
+
+/// Source of the current time used to decide whether the last observed lag can be reused and how
+/// long is left until a deadline, so that tests can drive time forward deterministically.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+}
+
+/// The monotonic system clock.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// The replication lag observed for each table, as returned by `WaitForReplication::current_lag`,
+/// or by `WaitForReplication::wait_for_replication` once it dropped. Tables that aren't configured
+/// to be waited on are missing from the latter.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ObservedLag(HashMap<&'static str, Duration>);
+
+impl ObservedLag {
+    pub fn get(&self, table: &str) -> Option<Duration> {
+        self.0.get(table).copied()
+    }
+
+    pub fn sync_queue(&self) -> Option<Duration> {
+        self.get(SYNC_QUEUE)
+    }
+
+    pub fn xdb_blobstore(&self) -> Option<Duration> {
+        self.get(XDB_BLOBSTORE)
+    }
 }
 
 #[derive(Clone)]
 pub struct WaitForReplication {
-    config_handle: ConfigHandle<ReplicationLagBlobstoreConfig>,
-    sync_queue_monitor: Arc<dyn ReplicaLagMonitor>,
-    xdb_blobstore_monitor: Arc<dyn ReplicaLagMonitor>,
+    fb: FacebookInit,
+    tables: Arc<ArcSwap<Vec<ReplicatedTable>>>,
+    /// Set when waiting on the tables of a storage config, to rebuild their monitors.
+    blobstore_source: Option<Arc<BlobstoreSource>>,
     state: Arc<Mutex<State>>,
+    max_total_wait: Option<Duration>,
+    backoff: Option<Backoff>,
+    adaptive_polling: bool,
+    dry_run: bool,
+    poll_observer: Option<PollObserver>,
+    clock: Arc<dyn Clock>,
+    /// The lag last recorded by the background sampler, if any is running.
+    sampled_lag: Arc<RwLock<Option<ObservedLag>>>,
+}
+
+/// What the tables of a storage config were built from.
+struct BlobstoreSource {
+    config_handle: ConfigHandle<ReplicationLagBlobstoreConfig>,
+    storage_config: std::sync::Mutex<StorageConfig>,
+    /// Simulates the replication lag of SQLite-backed storage, which doesn't replicate.
+    local_lag: Arc<ControlledReplicaLagMonitor>,
+}
+
+/// The weight of the lag of each blobstore, from `ReplicationLagBlobstoreConfig`.
+type BlobstoreWeights = Arc<dyn Fn(BlobstoreId) -> f64 + Send + Sync>;
+
+/// Monitors the replication lag of the MySQL blobstores of a multiplexed blobstore at once. The
+/// lag of each is scaled by its weight, so its maximum lag is the worst weighted lag across all of
+/// them.
+struct CompositeReplicaLagMonitor {
+    monitors: Vec<(BlobstoreId, Arc<dyn ReplicaLagMonitor>)>,
+    weights: BlobstoreWeights,
+}
+
+#[async_trait]
+impl ReplicaLagMonitor for CompositeReplicaLagMonitor {
+    async fn get_replica_lag(&self) -> Result<Vec<ReplicaLag>> {
+        let lags = try_join_all(self.monitors.iter().map(|(id, monitor)| async move {
+            let weight = (self.weights)(*id);
+            let lags = monitor.get_replica_lag().await?;
+            anyhow::Ok(
+                lags.into_iter()
+                    .map(move |lag| ReplicaLag::new(lag.delay.mul_f64(weight), lag.details)),
+            )
+        }))
+        .await?;
+        Ok(lags.into_iter().flatten().collect())
+    }
 }
 
-const CONFIGS_PATH: &str = "scm/mononoke/mysql/replication_lag/config";
+pub const CONFIGS_PATH: &str = "scm/mononoke/mysql/replication_lag/config";
+
+/// With adaptive polling, the interval between polls stays within this factor of the configured
+/// poll interval.
+const ADAPTIVE_POLL_RANGE: u32 = 4;
+
+/// Warn that replication may be stuck once the lag didn't drop below its lowest value for this
+/// many polls in a row.
+const STUCK_POLLS: u32 = 5;
 
-#[cfg(fbcode_build)]
+/// Blobstore weights are capped so that scaling a lag by them can't overflow.
+const MAX_BLOBSTORE_WEIGHT: f64 = 1000.0;
+
+/// Monitor every MySQL blobstore being multiplexed, whether sharded or unsharded, so that a mixed
+/// multiplexed config waits on the worst weighted lag across all of them.
 fn blobstore_monitor(
     my_admin: &MyAdmin,
     blobstores: Vec<(BlobstoreId, MultiplexedStoreType, BlobConfig)>,
+    weights: BlobstoreWeights,
 ) -> Arc<dyn ReplicaLagMonitor> {
-    blobstores
+    let monitors = blobstores
         .into_iter()
-        .find_map(|(_, _, config)| match config {
+        .filter_map(|(id, _, config)| match config {
             BlobConfig::Mysql {
                 remote: ShardableRemoteDatabaseConfig::Unsharded(remote),
-            } => Some(
+            } => Some((
+                id,
                 Arc::new(my_admin.single_shard_lag_monitor(remote.db_address))
                     as Arc<dyn ReplicaLagMonitor>,
-            ),
+            )),
             BlobConfig::Mysql {
                 remote: ShardableRemoteDatabaseConfig::Sharded(remote),
-            } => Some(Arc::new(my_admin.shardmap_lag_monitor(remote.shard_map))),
+            } => Some((
+                id,
+                Arc::new(my_admin.shardmap_lag_monitor(remote.shard_map))
+                    as Arc<dyn ReplicaLagMonitor>,
+            )),
             _ => None,
         })
-        .unwrap_or_else(|| Arc::new(NoReplicaLagMonitor()))
+        .collect::<Vec<_>>();
+    if monitors.is_empty() {
+        return Arc::new(NoReplicaLagMonitor());
+    }
+    Arc::new(CompositeReplicaLagMonitor { monitors, weights })
+}
+
+/// The weight of `id` in `weights`, or 1 if it has none or an invalid one.
+fn blobstore_weight(weights: Option<&BTreeMap<i64, f64>>, id: BlobstoreId) -> f64 {
+    let weight = i64::try_from(u64::from(id))
+        .ok()
+        .and_then(|id| weights?.get(&id))
+        .copied();
+    match weight {
+        Some(weight) if weight >= 0.0 => weight.min(MAX_BLOBSTORE_WEIGHT),
+        _ => 1.0,
+    }
+}
+
+/// Fluently assemble a `WaitForReplication`. To wait on the tables of a storage config, its
+/// config store, storage config and config name are all required. Everything else is optional.
+#[derive(Default)]
+pub struct WaitForReplicationBuilder<'a> {
+    config_store: Option<&'a ConfigStore>,
+    storage_config: Option<StorageConfig>,
+    config_name: Option<&'static str>,
+    configs_path: Option<String>,
+    tables: Vec<ReplicatedTable>,
+    max_total_wait: Option<Duration>,
+    backoff: Option<Backoff>,
+    adaptive_polling: bool,
+    dry_run: bool,
+    poll_observer: Option<PollObserver>,
+    clock: Option<Arc<dyn Clock>>,
+}
+
+impl<'a> WaitForReplicationBuilder<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The config store to read the replication lag config from.
+    pub fn with_config_store(mut self, config_store: &'a ConfigStore) -> Self {
+        self.config_store = Some(config_store);
+        self
+    }
+
+    /// The storage whose replicated tables are waited on.
+    pub fn with_storage_config(mut self, storage_config: StorageConfig) -> Self {
+        self.storage_config = Some(storage_config);
+        self
+    }
+
+    /// The name of the replication lag config, relative to the replication lag configs path.
+    pub fn with_config_name(mut self, config_name: &'static str) -> Self {
+        self.config_name = Some(config_name);
+        self
+    }
+
+    /// Look up the replication lag config under `configs_path` rather than under the default
+    /// `CONFIGS_PATH`, eg: to point tests at a fixture config tree.
+    pub fn with_configs_path(mut self, configs_path: impl Into<String>) -> Self {
+        self.configs_path = Some(configs_path.into());
+        self
+    }
+
+    /// Also wait on `table`, eg: another replicated database than the ones of the storage config.
+    pub fn with_table(mut self, table: ReplicatedTable) -> Self {
+        self.tables.push(table);
+        self
+    }
+
+    /// Bound how long `wait_for_replication` may block by default, after which it fails with
+    /// `WaitForReplicationError::Timeout`.
+    pub fn with_max_total_wait(mut self, max_total_wait: Duration) -> Self {
+        self.max_total_wait = Some(max_total_wait);
+        self
+    }
+
+    /// Poll less often while the replication lag of a table isn't dropping, to reduce the load
+    /// on the admin service during long catch-up windows. Fails if the multiplier of `backoff`
+    /// is invalid.
+    pub fn with_backoff(mut self, backoff: Backoff) -> Result<Self> {
+        backoff.validate()?;
+        self.backoff = Some(backoff);
+        Ok(self)
+    }
+
+    /// Scale the interval between polls with how far the replication lag is above its
+    /// threshold: poll less often while it's far above, and more often as it gets close. The
+    /// interval stays within a factor of `ADAPTIVE_POLL_RANGE` of the configured poll interval.
+    pub fn with_adaptive_polling(mut self) -> Self {
+        self.adaptive_polling = true;
+        self
+    }
+
+    /// Never block: query the replication lag once and log whether it would have been waited on
+    /// instead, eg: to validate a new replication lag config in production before enforcing it.
+    pub fn with_dry_run(mut self) -> Self {
+        self.dry_run = true;
+        self
+    }
+
+    /// Report every poll of the replication lag to `poll_observer`, eg: to stream the progress
+    /// of long waits to a custom telemetry system.
+    pub fn with_poll_observer(mut self, poll_observer: PollObserver) -> Self {
+        self.poll_observer = Some(poll_observer);
+        self
+    }
+
+    /// Use `clock` instead of the system clock to decide whether the last observed lag is recent
+    /// enough to skip querying it again, and how long is left until the deadline of
+    /// `wait_for_replication_until`.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    /// Build the monitors for the replicated tables of the storage config, if any, followed by
+    /// the tables added through `with_table`.
+    ///
+    /// Both `BlobConfig::Multiplexed` and `BlobConfig::MultiplexedWal` blobstores are monitored:
+    /// the sync queue (or WAL) through its remote queue database, and the XDB blobstore through
+    /// the MySQL blobstores being multiplexed. Any other blobstore isn't replicated through MySQL,
+    /// so waiting for it always succeeds immediately.
+    ///
+    /// Outside of fbcode_build there is no MyAdmin service, so each `db_address` must instead be
+    /// the MySQL URL of a replica, whose lag is read from `SHOW SLAVE STATUS`.
+    ///
+    /// If the queue database is a local SQLite database, both tables report the lag set through
+    /// `WaitForReplication::local_lag_monitor`, which is zero unless a test sets it.
+    pub fn build(self, fb: FacebookInit) -> Result<WaitForReplication> {
+        let (mut tables, blobstore_source) =
+            match (self.config_store, self.storage_config, self.config_name) {
+                (Some(config_store), Some(storage_config), Some(config_name)) => {
+                    let configs_path = self.configs_path.as_deref().unwrap_or(CONFIGS_PATH);
+                    let config_handle = config_store
+                        .get_config_handle(format!("{}/{}", configs_path, config_name))?;
+                    let local_lag = Arc::new(ControlledReplicaLagMonitor::new());
+                    let tables = blobstore_tables(
+                        fb,
+                        config_handle.clone(),
+                        storage_config.clone(),
+                        local_lag.clone(),
+                    )?;
+                    let source = BlobstoreSource {
+                        config_handle,
+                        storage_config: std::sync::Mutex::new(storage_config),
+                        local_lag,
+                    };
+                    (tables, Some(Arc::new(source)))
+                }
+                (None, None, None) => (vec![], None),
+                _ => bail!(
+                "A config store, a storage config and a config name are all required to wait for \
+                 the replication of a storage config"
+            ),
+            };
+        tables.extend(self.tables);
+        let mut state = State::new();
+        for table in &tables {
+            if state.insert(table.name, TableState::default()).is_some() {
+                bail!("Table {} is waited on more than once", table.name);
+            }
+        }
+        Ok(WaitForReplication {
+            fb,
+            tables: Arc::new(ArcSwap::from_pointee(tables)),
+            blobstore_source,
+            state: Arc::new(Mutex::new(state)),
+            max_total_wait: self.max_total_wait,
+            backoff: self.backoff,
+            adaptive_polling: self.adaptive_polling,
+            dry_run: self.dry_run,
+            poll_observer: self.poll_observer,
+            clock: self.clock.unwrap_or_else(|| Arc::new(SystemClock)),
+            sampled_lag: Arc::new(RwLock::new(None)),
+        })
+    }
+}
+
+/// The sync queue and XDB blobstore tables of `storage_config`.
+fn blobstore_tables(
+    fb: FacebookInit,
+    config_handle: ConfigHandle<ReplicationLagBlobstoreConfig>,
+    storage_config: StorageConfig,
+    local_lag: Arc<ControlledReplicaLagMonitor>,
+) -> Result<Vec<ReplicatedTable>> {
+    let weights_config = config_handle.clone();
+    let weights: BlobstoreWeights =
+        Arc::new(move |id| blobstore_weight(weights_config.get().blobstore_weights.as_ref(), id));
+    let (sync_queue_monitor, xdb_blobstore_monitor) = match storage_config.blobstore {
+        BlobConfig::Multiplexed {
+            blobstores,
+            queue_db: DatabaseConfig::Remote(remote),
+            ..
+        } => {
+            let my_admin = MyAdmin::new(fb)?;
+            let sync_queue = Arc::new(my_admin.single_shard_lag_monitor(remote.db_address))
+                as Arc<dyn ReplicaLagMonitor>;
+            let xdb_blobstore = blobstore_monitor(&my_admin, blobstores, weights);
+            (sync_queue, xdb_blobstore)
+        }
+        BlobConfig::MultiplexedWal {
+            blobstores,
+            queue_db: ShardedDatabaseConfig::Remote(remote),
+            ..
+        } => {
+            let my_admin = MyAdmin::new(fb)?;
+            let sync_queue = Arc::new(my_admin.shardmap_lag_monitor(remote.shard_map))
+                as Arc<dyn ReplicaLagMonitor>;
+            let xdb_blobstore = blobstore_monitor(&my_admin, blobstores, weights);
+            (sync_queue, xdb_blobstore)
+        }
+        BlobConfig::Multiplexed {
+            queue_db: DatabaseConfig::Local(_),
+            ..
+        }
+        | BlobConfig::MultiplexedWal {
+            queue_db: ShardedDatabaseConfig::Local(_),
+            ..
+        } => (
+            local_lag.clone() as Arc<dyn ReplicaLagMonitor>,
+            local_lag as Arc<dyn ReplicaLagMonitor>,
+        ),
+        _ => (
+            Arc::new(NoReplicaLagMonitor()) as Arc<dyn ReplicaLagMonitor>,
+            Arc::new(NoReplicaLagMonitor()) as Arc<dyn ReplicaLagMonitor>,
+        ),
+    };
+    let sync_queue_config = config_handle.clone();
+    Ok(vec![
+        ReplicatedTable::new(SYNC_QUEUE, sync_queue_monitor, move || {
+            sync_queue_config.get().sync_queue.clone()
+        }),
+        ReplicatedTable::new(XDB_BLOBSTORE, xdb_blobstore_monitor, move || {
+            config_handle.get().xdb_blobstore.clone()
+        }),
+    ])
 }
 
 impl WaitForReplication {
+    /// Shorthand for `WaitForReplicationBuilder` when only the required arguments are needed.
     pub fn new(
         fb: FacebookInit,
         config_store: &ConfigStore,
         storage_config: StorageConfig,
         config_name: &'static str,
     ) -> Result<Self> {
-        let config_handle =
-            config_store.get_config_handle(format!("{}/{}", CONFIGS_PATH, config_name))?;
-        let (sync_queue_monitor, xdb_blobstore_monitor) = match storage_config.blobstore {
-            BlobConfig::Multiplexed {
-                blobstores,
-                queue_db: DatabaseConfig::Remote(remote),
-                ..
-            } => {
-                #[cfg(fbcode_build)]
-                {
-                    let my_admin = MyAdmin::new(fb)?;
-                    let sync_queue = Arc::new(my_admin.single_shard_lag_monitor(remote.db_address))
-                        as Arc<dyn ReplicaLagMonitor>;
-                    let xdb_blobstore = blobstore_monitor(&my_admin, blobstores);
-                    (sync_queue, xdb_blobstore)
-                }
-                #[cfg(not(fbcode_build))]
-                {
-                    let _ = (fb, remote, blobstores);
-                    unimplemented!()
-                }
-            }
-            BlobConfig::MultiplexedWal {
-                blobstores,
-                queue_db: ShardedDatabaseConfig::Remote(remote),
-                ..
-            } => {
-                #[cfg(fbcode_build)]
-                {
-                    let my_admin = MyAdmin::new(fb)?;
-                    let sync_queue = Arc::new(my_admin.shardmap_lag_monitor(remote.shard_map))
-                        as Arc<dyn ReplicaLagMonitor>;
-                    let xdb_blobstore = blobstore_monitor(&my_admin, blobstores);
-                    (sync_queue, xdb_blobstore)
-                }
-                #[cfg(not(fbcode_build))]
-                {
-                    let _ = (fb, remote, blobstores);
-                    unimplemented!()
+        WaitForReplicationBuilder::new()
+            .with_config_store(config_store)
+            .with_storage_config(storage_config)
+            .with_config_name(config_name)
+            .build(fb)
+    }
+
+    /// Rebuild the monitors of the storage config tables if `storage_config` differs from the one
+    /// they were built from, eg: after a blobstore was added. Their replication lag configs are
+    /// already reloaded live. Returns whether the monitors were rebuilt.
+    pub fn reload_storage_config(&self, storage_config: StorageConfig) -> Result<bool> {
+        let source = self
+            .blobstore_source
+            .as_ref()
+            .context("Not waiting on the replication of a storage config")?;
+        let mut current = source.storage_config.lock().expect("lock poisoned");
+        if *current == storage_config {
+            return Ok(false);
+        }
+        let tables = blobstore_tables(
+            self.fb,
+            source.config_handle.clone(),
+            storage_config.clone(),
+            source.local_lag.clone(),
+        )?
+        .into_iter()
+        .chain(
+            self.tables
+                .load()
+                .iter()
+                .filter(|table| table.name != SYNC_QUEUE && table.name != XDB_BLOBSTORE)
+                .cloned(),
+        )
+        .collect();
+        self.tables.store(Arc::new(tables));
+        *current = storage_config;
+        Ok(true)
+    }
+
+    /// The monitor simulating the replication lag of a SQLite-backed storage config, eg: for tests
+    /// to make `wait_for_replication` block until they lower it. `None` if not waiting on the
+    /// replication of a storage config.
+    pub fn local_lag_monitor(&self) -> Option<Arc<ControlledReplicaLagMonitor>> {
+        Some(self.blobstore_source.as_ref()?.local_lag.clone())
+    }
+
+    /// Query the current replication lag of each table once, without waiting for it to drop.
+    pub async fn current_lag(&self) -> Result<ObservedLag> {
+        let tables = self.tables.load_full();
+        let lags = try_join_all(tables.iter().map(|table| async move {
+            let lag = table.monitor.get_max_replica_lag().await?;
+            anyhow::Ok((table.name, lag.delay))
+        }))
+        .await?;
+        Ok(ObservedLag(lags.into_iter().collect()))
+    }
+
+    /// Spawn a task recording the current replication lag of each table every `interval`, so
+    /// that it is reported even when nothing waits for replication. The last recorded lag is
+    /// available from `sampled_lag`. The task runs until the returned handle is aborted.
+    pub fn spawn_lag_sampler(&self, logger: Logger, interval: Duration) -> JoinHandle<()> {
+        let this = self.clone();
+        tokio::spawn(async move {
+            loop {
+                match this.current_lag().await {
+                    Ok(lag) => {
+                        for (name, lag) in &lag.0 {
+                            set_lag_stat(this.fb, name, *lag);
+                        }
+                        *this.sampled_lag.write().expect("lock poisoned") = Some(lag);
+                    }
+                    Err(e) => warn!(logger, "Failed to sample replication lag: {:?}", e),
                 }
+                tokio::time::sleep(interval).await;
             }
-            _ => (
-                Arc::new(NoReplicaLagMonitor()) as Arc<dyn ReplicaLagMonitor>,
-                Arc::new(NoReplicaLagMonitor()) as Arc<dyn ReplicaLagMonitor>,
-            ),
-        };
-        Ok(Self {
-            config_handle,
-            sync_queue_monitor,
-            xdb_blobstore_monitor,
-            state: Arc::new(Mutex::new(State::default())),
         })
     }
 
-    pub async fn wait_for_replication(&self, logger: &Logger) -> Result<()> {
-        let mut state_lock = self.state.lock().await;
-        let State {
-            last_sync_queue_lag,
-            last_xdb_blobstore_lag,
-        } = state_lock.deref_mut();
-        try_join!(
-            self.wait_for_table(
-                logger,
-                "sync queue",
-                last_sync_queue_lag,
-                &self.sync_queue_monitor,
-                || self.config_handle.get().sync_queue.clone(),
-            ),
-            self.wait_for_table(
-                logger,
-                "XDB blobstore",
-                last_xdb_blobstore_lag,
-                &self.xdb_blobstore_monitor,
-                || self.config_handle.get().xdb_blobstore.clone(),
-            ),
-        )?;
-        Ok(())
+    /// The replication lag last recorded by `spawn_lag_sampler`, `None` before the first sample.
+    pub fn sampled_lag(&self) -> Option<ObservedLag> {
+        self.sampled_lag.read().expect("lock poisoned").clone()
     }
 
-    async fn wait_for_table<'a>(
-        &'a self,
-        logger: &'a Logger,
-        name: &'static str,
-        last_lag: &'a mut Option<(Instant, Duration)>,
-        monitor: &'a Arc<dyn ReplicaLagMonitor>,
-        config_getter: impl Fn() -> Option<ReplicationLagTableConfig> + Sync,
-    ) -> Result<()> {
-        if let Some(raw_config) = config_getter() {
-            let max_replication_lag_allowed =
-                Duration::from_millis(raw_config.max_replication_lag_allowed_ms.try_into()?);
-            let poll_interval = Duration::from_millis(raw_config.poll_interval_ms.try_into()?);
-            match last_lag.as_mut() {
+    /// Like `wait_for_replication`, but don't block past `deadline`, eg: the deadline of the
+    /// request waiting for replication. Fails with `WaitForReplicationError::Timeout` once it is
+    /// reached, or once the bound set with `with_max_total_wait` is if that comes first.
+    pub async fn wait_for_replication_until(
+        &self,
+        logger: &Logger,
+        deadline: Instant,
+    ) -> Result<ObservedLag> {
+        let remaining = deadline.saturating_duration_since(self.clock.now());
+        let max_total_wait = match self.max_total_wait {
+            Some(max_total_wait) => std::cmp::min(max_total_wait, remaining),
+            None => remaining,
+        };
+        self.wait_for_replication(logger, Some(max_total_wait))
+            .await
+    }
+
+    /// Wait for the replication lag of every configured table to drop below its threshold.
+    ///
+    /// `max_total_wait` bounds how long this call may block, overriding the bound set with
+    /// `with_max_total_wait` if any.
+    ///
+    /// This is cancellation safe: the trend of the lag used for backoff and stuck detection is
+    /// reset at the start of every wait, and the last observed lag is only recorded once a wait
+    /// completes, so dropping the returned future at any point doesn't affect the next call. To
+    /// interrupt a wait on shutdown, race it against the shutdown signal, eg: with `select!`.
+    #[tracing::instrument(skip_all)]
+    pub async fn wait_for_replication(
+        &self,
+        logger: &Logger,
+        max_total_wait: Option<Duration>,
+    ) -> Result<ObservedLag> {
+        let mut state = self.state.lock().await;
+        let mut states = state
+            .iter_mut()
+            .map(|(name, table_state)| (*name, table_state))
+            .collect::<HashMap<_, _>>();
+        // Track which tables are done so that a timeout can report the ones still lagging.
+        let all_tables = self.tables.load_full();
+        let mut tables = all_tables
+            .iter()
+            .filter_map(|table| Some((table, states.remove(table.name)?, false)))
+            .collect::<Vec<_>>();
+        let wait = try_join_all(
+            tables
+                .iter_mut()
+                .map(|(table, table_state, done)| async move {
+                    let lag = self.wait_for_table(logger, table, table_state).await?;
+                    *done = true;
+                    anyhow::Ok((table.name, lag))
+                }),
+        );
+        let lags = match max_total_wait.or(self.max_total_wait) {
+            Some(max_total_wait) => {
+                let res = timeout(max_total_wait, wait).await;
+                match res {
+                    Ok(res) => res?,
+                    Err(_) => {
+                        let lagging = tables
+                            .iter()
+                            .filter(|(_, _, done)| !done)
+                            .map(|(table, _, _)| table.name)
+                            .collect::<Vec<_>>();
+                        return Err(WaitForReplicationError::Timeout(
+                            max_total_wait,
+                            lagging.join(", "),
+                        )
+                        .into());
+                    }
+                }
+            }
+            None => wait.await?,
+        };
+        Ok(ObservedLag(
+            lags.into_iter()
+                .filter_map(|(name, lag)| Some((name, lag?)))
+                .collect(),
+        ))
+    }
+
+    /// Wait for the replication lag of a table to drop below its configured threshold, returning
+    /// the last observed lag if the table is configured and enabled. Disabling the table while
+    /// waiting stops the wait. Fails with
+    /// `WaitForReplicationError::TooManyPolls` if the config's `max_polls` is exhausted first.
+    /// In dry-run mode, the lag is only queried and logged.
+    async fn wait_for_table(
+        &self,
+        logger: &Logger,
+        table: &ReplicatedTable,
+        state: &mut TableState,
+    ) -> Result<Option<Duration>> {
+        let name = table.name;
+        let monitor = &table.monitor;
+        let config_getter = &table.config;
+        if let Some(raw_config) = config_getter().filter(table_enabled) {
+            let mut config = table_config(&raw_config)
+                .with_context(|| format!("Invalid replication lag config for {}", name))?;
+            let now = self.clock.now();
+            match state.last_lag.as_mut() {
                 // If queried too recently, just assume it's all ok.
                 Some((instant, duration))
-                    if instant.elapsed() < poll_interval
-                        && *duration < max_replication_lag_allowed =>
+                    if now.saturating_duration_since(*instant) < config.poll_interval
+                        && *duration < config.max_replication_lag_allowed =>
                 {
-                    return Ok(());
+                    return Ok(Some(*duration));
                 }
                 // If impossible to have surpassed replication_lag, don't query
                 Some((instant, duration))
-                    if *duration + instant.elapsed() < max_replication_lag_allowed =>
+                    if *duration + now.saturating_duration_since(*instant)
+                        < config.max_replication_lag_allowed =>
                 {
-                    return Ok(());
+                    return Ok(Some(*duration));
                 }
                 _ => {}
             }
+            if self.dry_run {
+                let lag = monitor.get_max_replica_lag().await?.delay;
+                let verdict = if lag < config.max_replication_lag_allowed {
+                    "Would not wait for replication lag".to_string()
+                } else {
+                    format!(
+                        "Would wait for replication lag to drop by at least {:?}",
+                        lag - config.max_replication_lag_allowed
+                    )
+                };
+                info!(
+                    logger,
+                    "[dry-run] {}", verdict;
+                    "table" => name,
+                    "lag_ms" => millis(lag),
+                    "max_lag_ms" => millis(config.max_replication_lag_allowed),
+                );
+                state.last_lag = Some((self.clock.now(), lag));
+                set_lag_stat(self.fb, name, lag);
+                return Ok(Some(lag));
+            }
+            let start = Instant::now();
             info!(
                 logger,
-                "Waiting for replication lag on {} to drop below {:?}",
-                name,
-                max_replication_lag_allowed
+                "Waiting for replication lag to drop";
+                "table" => name,
+                "max_lag_ms" => millis(config.max_replication_lag_allowed),
+                "poll_interval_ms" => millis(config.poll_interval),
             );
-            let new_last_lag = monitor
-                .wait_for_replication(&|| {
-                    // Get the most up to date config, but default to the previous one
-                    // in case of errors.
-                    let (max_replication_lag_allowed, poll_interval) =
-                        if let Some(raw_config) = config_getter() {
-                            (
-                                raw_config
-                                    .max_replication_lag_allowed_ms
-                                    .try_into()
-                                    .map_or(max_replication_lag_allowed, Duration::from_millis),
-                                raw_config
-                                    .poll_interval_ms
-                                    .try_into()
-                                    .map_or(poll_interval, Duration::from_millis),
-                            )
-                        } else {
-                            (max_replication_lag_allowed, poll_interval)
-                        };
-                    WaitForReplicationConfig::new(
-                        max_replication_lag_allowed,
-                        poll_interval,
+            let span = info_span!(
+                "wait_for_table",
+                table = name,
+                max_lag = ?config.max_replication_lag_allowed,
+                poll_interval = ?config.poll_interval,
+                observed_lag = tracing::field::Empty,
+            );
+            // A previous wait may have been dropped halfway, don't back off based on its polls.
+            state.stop_lagging();
+            let mut blocked = false;
+            let mut polls = 0;
+            let lag = async {
+                loop {
+                    let lag = monitor.get_max_replica_lag().await?;
+                    polls += 1;
+                    info!(
                         logger,
-                    )
+                        "{}", lag;
+                        "table" => name,
+                        "lag_ms" => millis(lag.delay),
+                    );
+                    if let Some(poll_observer) = &self.poll_observer {
+                        poll_observer(name, lag.delay);
+                    }
+                    // Get the most up to date config, but default to the previous one in case of
+                    // errors.
+                    let raw_config = config_getter();
+                    if matches!(&raw_config, Some(raw_config) if !table_enabled(raw_config)) {
+                        info!(logger, "Waiting for replication lag was disabled"; "table" => name);
+                        state.stop_lagging();
+                        break anyhow::Ok(lag.delay);
+                    }
+                    if let Some(Ok(new_config)) = raw_config.as_ref().map(table_config) {
+                        config = new_config;
+                    }
+                    if lag.delay < config.max_replication_lag_allowed {
+                        state.stop_lagging();
+                        break anyhow::Ok(lag.delay);
+                    }
+                    if matches!(config.max_polls, Some(max_polls) if polls >= max_polls) {
+                        state.stop_lagging();
+                        return Err(
+                            WaitForReplicationError::TooManyPolls(name, polls, lag.delay).into(),
+                        );
+                    }
+                    let (lowest_lag, polls_since_improved) = match state.stuck {
+                        Some((lowest_lag, since)) if lag.delay >= lowest_lag => {
+                            (lowest_lag, since + 1)
+                        }
+                        _ => (lag.delay, 0),
+                    };
+                    state.stuck = Some((lowest_lag, polls_since_improved));
+                    if polls_since_improved == STUCK_POLLS {
+                        warn!(
+                            logger,
+                            "Replication lag hasn't dropped in {} polls, replication may be stuck",
+                            polls_since_improved;
+                            "table" => name,
+                            "lag_ms" => millis(lag.delay),
+                            "lowest_lag_ms" => millis(lowest_lag),
+                        );
+                        STATS::stuck.add_value(1, (stats_key(name),));
+                    }
+                    let poll_interval = if self.adaptive_polling {
+                        (lag.delay - config.max_replication_lag_allowed).clamp(
+                            config.poll_interval / ADAPTIVE_POLL_RANGE,
+                            config.poll_interval * ADAPTIVE_POLL_RANGE,
+                        )
+                    } else {
+                        config.poll_interval
+                    };
+                    let interval = match (self.backoff, state.lagging) {
+                        (Some(backoff), Some((previous_lag, previous_interval)))
+                            if lag.delay >= previous_lag =>
+                        {
+                            backoff.next_interval(
+                                previous_interval,
+                                std::cmp::max(backoff.max_interval, poll_interval),
+                            )
+                        }
+                        _ => poll_interval,
+                    };
+                    state.lagging = Some((lag.delay, interval));
+                    blocked = true;
+                    // Wait before polling again.
+                    tokio::time::sleep(interval).await;
+                }
+            }
+            .instrument(span.clone())
+            .await?;
+            // From the first log of the wait to the lag dropping below the threshold.
+            let elapsed: i64 = start.elapsed().as_millis().try_into().unwrap_or(i64::MAX);
+            span.record("observed_lag", &tracing::field::debug(lag));
+            drop(span);
+            state.last_lag = Some((self.clock.now(), lag));
+
+            let table = stats_key(name);
+            STATS::wait_ms.add_value(elapsed, (table.clone(),));
+            STATS::wait_duration_ms.add_value(elapsed, (table.clone(),));
+            if blocked {
+                STATS::blocked.add_value(1, (table,));
+            }
+            set_lag_stat(self.fb, name, lag);
+            Ok(Some(lag))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+/// Rebuilds the monitors of a `WaitForReplication` when its storage config changes, if the
+/// `wait_for_replication_reload_monitors` tunable is enabled. Register it with
+/// `MononokeConfigs::register_for_update`.
+pub struct MonitorsReloader<F> {
+    wait_for_replication: WaitForReplication,
+    logger: Logger,
+    /// Find the storage config being waited on in the updated configs.
+    storage_config: F,
+}
+
+impl<F> MonitorsReloader<F>
+where
+    F: Fn(&RepoConfigs, &StorageConfigs) -> Option<StorageConfig> + Send + Sync,
+{
+    pub fn new(
+        wait_for_replication: WaitForReplication,
+        logger: Logger,
+        storage_config: F,
+    ) -> Self {
+        Self {
+            wait_for_replication,
+            logger,
+            storage_config,
+        }
+    }
+}
+
+#[async_trait]
+impl<F> ConfigUpdateReceiver for MonitorsReloader<F>
+where
+    F: Fn(&RepoConfigs, &StorageConfigs) -> Option<StorageConfig> + Send + Sync,
+{
+    async fn apply_update(
+        &self,
+        repo_configs: Arc<RepoConfigs>,
+        storage_configs: Arc<StorageConfigs>,
+    ) -> Result<()> {
+        if !tunables().get_wait_for_replication_reload_monitors() {
+            return Ok(());
+        }
+        if let Some(storage_config) = (self.storage_config)(&repo_configs, &storage_configs) {
+            if self
+                .wait_for_replication
+                .reload_storage_config(storage_config)?
+            {
+                info!(self.logger, "Reloaded replication lag monitors");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A duration in milliseconds, for structured logging.
+fn millis(duration: Duration) -> u64 {
+    duration.as_millis().try_into().unwrap_or(u64::MAX)
+}
+
+/// Name of a table in the stats keys, eg: "sync queue" becomes "sync_queue".
+fn stats_key(name: &str) -> String {
+    name.to_lowercase().replace(' ', "_")
+}
+
+fn set_lag_stat(fb: FacebookInit, name: &str, lag: Duration) {
+    STATS::lag_ms.set_value(
+        fb,
+        lag.as_millis().try_into().unwrap_or(i64::MAX),
+        (stats_key(name),),
+    );
+}
+
+/// Tables are waited on unless explicitly disabled.
+fn table_enabled(raw_config: &ReplicationLagTableConfig) -> bool {
+    raw_config.enabled.unwrap_or(true)
+}
+
+/// The validated replication lag config of a table.
+struct TableConfig {
+    max_replication_lag_allowed: Duration,
+    poll_interval: Duration,
+    /// Give up after this many polls with the lag above the threshold, `None` if unlimited.
+    max_polls: Option<u32>,
+}
+
+fn table_config(raw_config: &ReplicationLagTableConfig) -> Result<TableConfig> {
+    let max_polls = match raw_config.max_polls {
+        Some(max_polls) if max_polls > 0 => Some(max_polls as u32),
+        Some(max_polls) => bail!("Invalid max_polls: {}", max_polls),
+        None => None,
+    };
+    Ok(TableConfig {
+        max_replication_lag_allowed: positive_millis(
+            "max_replication_lag_allowed_ms",
+            raw_config.max_replication_lag_allowed_ms,
+        )?,
+        poll_interval: positive_millis("poll_interval_ms", raw_config.poll_interval_ms)?,
+        max_polls,
+    })
+}
+
+/// A zero max lag could never be satisfied, and a zero poll interval would query the lag in a busy
+/// loop, so both must be strictly positive.
+fn positive_millis(field: &str, value: i32) -> Result<Duration> {
+    let millis: u64 = value
+        .try_into()
+        .with_context(|| format!("Invalid {}: {}", field, value))?;
+    if millis == 0 {
+        bail!("Invalid {}: must not be 0", field);
+    }
+    Ok(Duration::from_millis(millis))
+}
+
+#[cfg(test)]
+mod test {
+    use std::num::NonZeroU64;
+    use std::num::NonZeroUsize;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+
+    use cached_config::ModificationTime;
+    use cached_config::TestSource;
+    use metaconfig_types::LocalDatabaseConfig;
+    use metaconfig_types::MetadataDatabaseConfig;
+    use metaconfig_types::MultiplexId;
+    use slog::o;
+    use slog::Discard;
+
+    use super::*;
+
+    struct FakeClock(std::sync::Mutex<Instant>);
+
+    impl FakeClock {
+        fn advance(&self, duration: Duration) {
+            *self.0.lock().unwrap() += duration;
+        }
+    }
+
+    impl Clock for FakeClock {
+        fn now(&self) -> Instant {
+            *self.0.lock().unwrap()
+        }
+    }
+
+    struct CountingMonitor {
+        lag: Duration,
+        queries: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl ReplicaLagMonitor for CountingMonitor {
+        async fn get_replica_lag(&self) -> Result<Vec<ReplicaLag>> {
+            self.queries.fetch_add(1, Ordering::Relaxed);
+            Ok(vec![ReplicaLag::new(self.lag, None)])
+        }
+    }
+
+    /// Waits on the sync queue only, with a max lag of 10s polled every 2s, after having observed
+    /// a lag of `last_lag` at the current time of `clock`.
+    async fn wait_with_last_lag(
+        fb: FacebookInit,
+        monitor: Arc<CountingMonitor>,
+        clock: Arc<FakeClock>,
+        last_lag: Duration,
+    ) -> Result<WaitForReplication> {
+        let wait = WaitForReplicationBuilder::new()
+            .with_table(ReplicatedTable::new(SYNC_QUEUE, monitor, || {
+                Some(ReplicationLagTableConfig {
+                    max_replication_lag_allowed_ms: 10000,
+                    poll_interval_ms: 2000,
+                    max_polls: None,
+                    enabled: None,
+                })
+            }))
+            .with_clock(clock.clone())
+            .build(fb)?;
+        wait.state
+            .lock()
+            .await
+            .get_mut(SYNC_QUEUE)
+            .unwrap()
+            .last_lag = Some((clock.now(), last_lag));
+        Ok(wait)
+    }
+
+    fn counting_monitor() -> Arc<CountingMonitor> {
+        Arc::new(CountingMonitor {
+            lag: Duration::from_secs(1),
+            queries: AtomicUsize::new(0),
+        })
+    }
+
+    #[fbinit::test]
+    async fn test_skip_query_when_queried_recently(fb: FacebookInit) -> Result<()> {
+        let logger = Logger::root(Discard, o!());
+        let monitor = counting_monitor();
+        let clock = Arc::new(FakeClock(std::sync::Mutex::new(Instant::now())));
+        let wait =
+            wait_with_last_lag(fb, monitor.clone(), clock.clone(), Duration::from_secs(9)).await?;
+
+        // Within the poll interval, the last lag is reused even though it could have grown past
+        // the threshold by now.
+        clock.advance(Duration::from_secs(1));
+        let lag = wait.wait_for_replication(&logger, None).await?;
+        assert_eq!(lag.sync_queue(), Some(Duration::from_secs(9)));
+        assert_eq!(monitor.queries.load(Ordering::Relaxed), 0);
+
+        // Past the poll interval, the lag is queried again.
+        clock.advance(Duration::from_secs(1));
+        let lag = wait.wait_for_replication(&logger, None).await?;
+        assert_eq!(lag.sync_queue(), Some(Duration::from_secs(1)));
+        assert_eq!(monitor.queries.load(Ordering::Relaxed), 1);
+        Ok(())
+    }
+
+    #[fbinit::test]
+    async fn test_skip_query_when_lag_cannot_exceed_threshold(fb: FacebookInit) -> Result<()> {
+        let logger = Logger::root(Discard, o!());
+        let monitor = counting_monitor();
+        let clock = Arc::new(FakeClock(std::sync::Mutex::new(Instant::now())));
+        let wait =
+            wait_with_last_lag(fb, monitor.clone(), clock.clone(), Duration::from_secs(2)).await?;
+
+        // Past the poll interval, but the lag can't have grown from 2s past 10s in 7s.
+        clock.advance(Duration::from_secs(7));
+        let lag = wait.wait_for_replication(&logger, None).await?;
+        assert_eq!(lag.sync_queue(), Some(Duration::from_secs(2)));
+        assert_eq!(monitor.queries.load(Ordering::Relaxed), 0);
+
+        // After 8s it could have, so the lag is queried again.
+        clock.advance(Duration::from_secs(1));
+        let lag = wait.wait_for_replication(&logger, None).await?;
+        assert_eq!(lag.sync_queue(), Some(Duration::from_secs(1)));
+        assert_eq!(monitor.queries.load(Ordering::Relaxed), 1);
+        Ok(())
+    }
+
+    /// Waits on a SQLite-backed multiplexed blobstore, whose sync queue allows a lag of 1s polled
+    /// every 10ms.
+    fn wait_on_local_storage(fb: FacebookInit) -> Result<WaitForReplication> {
+        wait_on_local_storage_with(fb, |builder| Ok(builder))
+    }
+
+    /// Like `wait_on_local_storage`, with the other options set by `configure`.
+    fn wait_on_local_storage_with(
+        fb: FacebookInit,
+        configure: impl for<'a> FnOnce(
+            WaitForReplicationBuilder<'a>,
+        ) -> Result<WaitForReplicationBuilder<'a>>,
+    ) -> Result<WaitForReplication> {
+        let test_source = Arc::new(TestSource::new());
+        test_source.insert_config(
+            &format!("{}/test", CONFIGS_PATH),
+            r#"{"sync_queue": {"max_replication_lag_allowed_ms": 1000, "poll_interval_ms": 10}}"#,
+            ModificationTime::UnixTimestamp(0),
+        );
+        let config_store = ConfigStore::new(test_source, None, None);
+        let local = LocalDatabaseConfig {
+            path: "/tmp".into(),
+        };
+        let storage_config = StorageConfig {
+            blobstore: BlobConfig::Multiplexed {
+                multiplex_id: MultiplexId::new(1),
+                scuba_table: None,
+                multiplex_scuba_table: None,
+                blobstores: vec![],
+                minimum_successful_writes: NonZeroUsize::new(1).unwrap(),
+                not_present_read_quorum: NonZeroUsize::new(1).unwrap(),
+                scuba_sample_rate: NonZeroU64::new(100).unwrap(),
+                queue_db: DatabaseConfig::Local(local.clone()),
+            },
+            metadata: MetadataDatabaseConfig::Local(local),
+            ephemeral_blobstore: None,
+        };
+        configure(
+            WaitForReplicationBuilder::new()
+                .with_config_store(&config_store)
+                .with_storage_config(storage_config)
+                .with_config_name("test"),
+        )?
+        .build(fb)
+    }
+
+    #[fbinit::test]
+    async fn test_wait_until_local_lag_drops(fb: FacebookInit) -> Result<()> {
+        let logger = Logger::root(Discard, o!());
+        let wait = wait_on_local_storage(fb)?;
+        let monitor = wait.local_lag_monitor().unwrap();
+
+        // Above the threshold, the wait blocks.
+        monitor.set_lag(Duration::from_secs(5));
+        let res = timeout(
+            Duration::from_millis(100),
+            wait.wait_for_replication(&logger, None),
+        )
+        .await;
+        assert!(res.is_err());
+
+        // It completes once the lag drops below the threshold.
+        let lower = tokio::spawn({
+            let monitor = monitor.clone();
+            async move {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                monitor.set_lag(Duration::from_millis(500));
+            }
+        });
+        let lag = wait
+            .wait_for_replication(&logger, Some(Duration::from_secs(10)))
+            .await?;
+        assert_eq!(lag.sync_queue(), Some(Duration::from_millis(500)));
+        lower.await?;
+        Ok(())
+    }
+
+    #[fbinit::test]
+    async fn test_wait_times_out_on_local_lag(fb: FacebookInit) -> Result<()> {
+        let logger = Logger::root(Discard, o!());
+        let wait = wait_on_local_storage(fb)?;
+        wait.local_lag_monitor()
+            .unwrap()
+            .set_lag(Duration::from_secs(5));
+
+        let err = wait
+            .wait_for_replication(&logger, Some(Duration::from_millis(100)))
+            .await
+            .unwrap_err();
+        match err.downcast_ref::<WaitForReplicationError>() {
+            Some(WaitForReplicationError::Timeout(_, lagging)) => assert_eq!(lagging, SYNC_QUEUE),
+            _ => panic!("unexpected error: {:?}", err),
+        }
+        Ok(())
+    }
+
+    #[fbinit::test]
+    async fn test_wait_until_deadline(fb: FacebookInit) -> Result<()> {
+        let logger = Logger::root(Discard, o!());
+        let clock = Arc::new(FakeClock(std::sync::Mutex::new(Instant::now())));
+        let wait = wait_on_local_storage_with(fb, |builder| {
+            Ok(builder
+                .with_max_total_wait(Duration::from_secs(10))
+                .with_clock(clock.clone()))
+        })?;
+        let monitor = wait.local_lag_monitor().unwrap();
+
+        // Once the clock of the wait reached the deadline, it gives up right away.
+        monitor.set_lag(Duration::from_secs(5));
+        let deadline = clock.now() + Duration::from_secs(1);
+        clock.advance(Duration::from_secs(1));
+        let err = wait
+            .wait_for_replication_until(&logger, deadline)
+            .await
+            .unwrap_err();
+        match err.downcast_ref::<WaitForReplicationError>() {
+            Some(WaitForReplicationError::Timeout(max_total_wait, _)) => {
+                assert_eq!(*max_total_wait, Duration::ZERO)
+            }
+            _ => panic!("unexpected error: {:?}", err),
+        }
+
+        // Before the deadline, it waits as usual.
+        monitor.set_lag(Duration::from_millis(500));
+        let lag = wait
+            .wait_for_replication_until(&logger, clock.now() + Duration::from_secs(10))
+            .await?;
+        assert_eq!(lag.sync_queue(), Some(Duration::from_millis(500)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_weighted_blobstore_lag() -> Result<()> {
+        let weights = BTreeMap::from([(1, 0.0), (2, 2.0), (3, -1.0)]);
+        let monitor = CompositeReplicaLagMonitor {
+            monitors: [(1, 10), (2, 2), (3, 3)]
+                .into_iter()
+                .map(|(id, lag)| {
+                    let monitor = Arc::new(CountingMonitor {
+                        lag: Duration::from_secs(lag),
+                        queries: AtomicUsize::new(0),
+                    }) as Arc<dyn ReplicaLagMonitor>;
+                    (BlobstoreId::new(id), monitor)
                 })
-                .await?;
-            *last_lag = Some((Instant::now(), new_last_lag.delay));
+                .collect(),
+            weights: Arc::new(move |id| blobstore_weight(Some(&weights), id)),
+        };
+
+        // The first blobstore is ignored, the second one weighs double, and the invalid weight of
+        // the third one defaults to 1.
+        let lag = monitor.get_max_replica_lag().await?;
+        assert_eq!(lag.delay, Duration::from_secs(4));
+        Ok(())
+    }
+
+    fn backoff(multiplier: f64) -> Backoff {
+        Backoff {
+            multiplier,
+            max_interval: Duration::from_secs(60),
         }
+    }
+
+    #[fbinit::test]
+    async fn test_invalid_backoff(fb: FacebookInit) -> Result<()> {
+        for multiplier in [0.5, -2.0, f64::NAN, f64::INFINITY] {
+            assert!(wait_on_local_storage_with(fb, |builder| builder
+                .with_backoff(backoff(multiplier)))
+            .is_err());
+        }
+        assert!(
+            wait_on_local_storage_with(fb, |builder| builder.with_backoff(backoff(1.0))).is_ok()
+        );
+        Ok(())
+    }
+
+    #[fbinit::test]
+    async fn test_wait_after_cancel(fb: FacebookInit) -> Result<()> {
+        let logger = Logger::root(Discard, o!());
+        // The interval doubles from 10ms after every poll where the lag didn't drop.
+        let wait = wait_on_local_storage_with(fb, |builder| {
+            builder.with_backoff(Backoff {
+                multiplier: 2.0,
+                max_interval: Duration::from_secs(3600),
+            })
+        })?;
+        let monitor = wait.local_lag_monitor().unwrap();
+
+        // Drop the wait once it backed off to polling every 640ms.
+        monitor.set_lag(Duration::from_secs(5));
+        let res = timeout(
+            Duration::from_secs(1),
+            wait.wait_for_replication(&logger, None),
+        )
+        .await;
+        assert!(res.is_err());
+
+        // The next wait polls every 10ms again, rather than resuming the backoff of the dropped
+        // one and waiting over 1s before its second poll, so it notices the lag dropping.
+        let lower = tokio::spawn({
+            let monitor = monitor.clone();
+            async move {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                monitor.set_lag(Duration::from_millis(500));
+            }
+        });
+        let lag = wait
+            .wait_for_replication(&logger, Some(Duration::from_secs(1)))
+            .await?;
+        assert_eq!(lag.sync_queue(), Some(Duration::from_millis(500)));
+        lower.await?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_backoff_next_interval() {
+        let max_interval = Duration::from_secs(60);
+        assert_eq!(
+            backoff(2.0).next_interval(Duration::from_secs(1), max_interval),
+            Duration::from_secs(2)
+        );
+        assert_eq!(
+            backoff(2.0).next_interval(Duration::from_secs(40), max_interval),
+            max_interval
+        );
+        // Growing past what a `Duration` can hold is capped rather than panicking.
+        assert_eq!(
+            backoff(f64::MAX).next_interval(Duration::MAX, max_interval),
+            max_interval
+        );
+    }
+
+    /// A max lag of 1s, polled every 10ms.
+    fn lag_config(max_polls: Option<i32>, enabled: Option<bool>) -> ReplicationLagTableConfig {
+        ReplicationLagTableConfig {
+            max_replication_lag_allowed_ms: 1000,
+            poll_interval_ms: 10,
+            max_polls,
+            enabled,
+        }
+    }
+
+    #[fbinit::test]
+    async fn test_too_many_polls(fb: FacebookInit) -> Result<()> {
+        let logger = Logger::root(Discard, o!());
+        let monitor = Arc::new(CountingMonitor {
+            lag: Duration::from_secs(5),
+            queries: AtomicUsize::new(0),
+        });
+        let wait = WaitForReplicationBuilder::new()
+            .with_table(ReplicatedTable::new(SYNC_QUEUE, monitor.clone(), || {
+                Some(lag_config(Some(3), None))
+            }))
+            .build(fb)?;
+
+        let err = wait
+            .wait_for_replication(&logger, Some(Duration::from_secs(10)))
+            .await
+            .unwrap_err();
+        match err.downcast_ref::<WaitForReplicationError>() {
+            Some(WaitForReplicationError::TooManyPolls(table, polls, lag)) => {
+                assert_eq!((*table, *polls, *lag), (SYNC_QUEUE, 3, monitor.lag))
+            }
+            _ => panic!("unexpected error: {:?}", err),
+        }
+        assert_eq!(monitor.queries.load(Ordering::Relaxed), 3);
+        Ok(())
+    }
+
+    #[fbinit::test]
+    async fn test_enabled(fb: FacebookInit) -> Result<()> {
+        let logger = Logger::root(Discard, o!());
+        let monitor = Arc::new(CountingMonitor {
+            lag: Duration::from_secs(5),
+            queries: AtomicUsize::new(0),
+        });
+        let enabled = Arc::new(AtomicBool::new(false));
+        let wait = WaitForReplicationBuilder::new()
+            .with_table(ReplicatedTable::new(SYNC_QUEUE, monitor.clone(), {
+                let enabled = enabled.clone();
+                move || Some(lag_config(None, Some(enabled.load(Ordering::Relaxed))))
+            }))
+            .build(fb)?;
+
+        // Disabled tables aren't waited on, nor queried.
+        let lag = wait
+            .wait_for_replication(&logger, Some(Duration::from_secs(10)))
+            .await?;
+        assert_eq!(lag.sync_queue(), None);
+        assert_eq!(monitor.queries.load(Ordering::Relaxed), 0);
+
+        // Disabling the table while waiting on it stops the wait.
+        enabled.store(true, Ordering::Relaxed);
+        let disable = tokio::spawn({
+            let enabled = enabled.clone();
+            async move {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                enabled.store(false, Ordering::Relaxed);
+            }
+        });
+        let lag = wait
+            .wait_for_replication(&logger, Some(Duration::from_secs(10)))
+            .await?;
+        assert_eq!(lag.sync_queue(), Some(monitor.lag));
+        disable.await?;
+        Ok(())
+    }
+
+    /// The number of polls since the lag of the sync queue last improved, as tracked for stuck
+    /// detection.
+    async fn polls_since_improved(wait: &WaitForReplication) -> Option<u32> {
+        let state = wait.state.lock().await;
+        state[SYNC_QUEUE].stuck.map(|(_, since)| since)
+    }
+
+    #[fbinit::test]
+    async fn test_stuck_detection(fb: FacebookInit) -> Result<()> {
+        let logger = Logger::root(Discard, o!());
+        let monitor = Arc::new(ControlledReplicaLagMonitor::new());
+        monitor.set_lag(Duration::from_secs(5));
+        // Lower the lag by 10ms after every poll, while improving.
+        let improving = Arc::new(AtomicBool::new(true));
+        let poll_observer: PollObserver = Arc::new({
+            let monitor = monitor.clone();
+            let improving = improving.clone();
+            move |_, lag| {
+                if improving.load(Ordering::Relaxed) {
+                    monitor.set_lag(lag - Duration::from_millis(10));
+                }
+            }
+        });
+        let wait = WaitForReplicationBuilder::new()
+            .with_table(ReplicatedTable::new(SYNC_QUEUE, monitor.clone(), || {
+                Some(lag_config(None, None))
+            }))
+            .with_poll_observer(poll_observer)
+            .build(fb)?;
+
+        // Slow but catching up isn't stuck.
+        let res = timeout(
+            Duration::from_millis(200),
+            wait.wait_for_replication(&logger, None),
+        )
+        .await;
+        assert!(res.is_err());
+        assert_eq!(polls_since_improved(&wait).await, Some(0));
+
+        // A lag that stops dropping is.
+        improving.store(false, Ordering::Relaxed);
+        let res = timeout(
+            Duration::from_millis(200),
+            wait.wait_for_replication(&logger, None),
+        )
+        .await;
+        assert!(res.is_err());
+        assert!(polls_since_improved(&wait).await >= Some(STUCK_POLLS));
+        Ok(())
+    }
+
+    #[fbinit::test]
+    async fn test_dry_run(fb: FacebookInit) -> Result<()> {
+        let logger = Logger::root(Discard, o!());
+        let wait = wait_on_local_storage_with(fb, |builder| Ok(builder.with_dry_run()))?;
+        wait.local_lag_monitor()
+            .unwrap()
+            .set_lag(Duration::from_secs(5));
+
+        // Above the threshold, the lag is returned without waiting for it to drop.
+        let lag = wait
+            .wait_for_replication(&logger, Some(Duration::from_secs(10)))
+            .await?;
+        assert_eq!(lag.sync_queue(), Some(Duration::from_secs(5)));
         Ok(())
     }
 }
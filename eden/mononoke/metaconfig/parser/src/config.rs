@@ -33,6 +33,7 @@ use repos::RawRepoConfig;
 use repos::RawRepoConfigs;
 use repos::RawRepoDefinition;
 use repos::RawStorageConfig;
+use serde::Serialize;
 
 use crate::convert::Convert;
 use crate::errors::ConfigurationError;
@@ -52,7 +53,7 @@ pub fn load_common_config(
 }
 
 /// Holds configuration for repostories.
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize)]
 pub struct RepoConfigs {
     /// Configs for all repositories
     pub repos: HashMap<String, RepoConfig>,
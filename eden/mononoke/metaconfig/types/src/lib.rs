@@ -39,6 +39,7 @@ use mysql_common::value::convert::ParseIr;
 use regex::Regex;
 use scuba::ScubaValue;
 use serde_derive::Deserialize;
+use serde_derive::Serialize;
 use sql::mysql;
 use sql::mysql_async::FromValueError;
 use sql::mysql_async::Value;
@@ -86,8 +87,17 @@ impl PartialEq for ComparableRegex {
 
 impl Eq for ComparableRegex {}
 
+impl serde::Serialize for ComparableRegex {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
 /// Structure representing general purpose identity.
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
 pub struct Identity {
     /// Type of this identity.
     pub id_type: String,
@@ -96,7 +106,7 @@ pub struct Identity {
 }
 
 /// Configuration for how blobs are redacted
-#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize)]
 pub struct RedactionConfig {
     /// Which blobstore should be used to fetch the redacted key lists
     pub blobstore: BlobConfig,
@@ -108,7 +118,7 @@ pub struct RedactionConfig {
 
 /// Configuration for all repos
 #[facet::facet]
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
 pub struct CommonConfig {
     /// Hipster tier that is permitted to act as a trusted proxy.
     pub trusted_parties_hipster_tier: Option<String>,
@@ -130,7 +140,7 @@ pub struct CommonConfig {
 }
 
 /// Configuration for logging of censored blobstore accesses
-#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize)]
 pub struct CensoredScubaParams {
     /// Scuba table for logging redacted file accesses
     pub table: Option<String>,
@@ -140,7 +150,7 @@ pub struct CensoredScubaParams {
 
 /// Configuration of a single repository
 #[facet::facet]
-#[derive(Debug, Default, Clone, Eq, PartialEq)]
+#[derive(Debug, Default, Clone, Eq, PartialEq, Serialize)]
 pub struct RepoConfig {
     /// If false, this repo config is completely ignored.
     pub enabled: bool,
@@ -229,7 +239,7 @@ pub struct RepoConfig {
 }
 
 /// Indicates types of commit hashes used in a repo context.
-#[derive(Eq, Clone, Debug, PartialEq)]
+#[derive(Eq, Clone, Debug, PartialEq, Serialize)]
 pub enum CommitIdentityScheme {
     /// Hashes are Mercurial hashes.
     HG,
@@ -248,7 +258,7 @@ impl Default for CommitIdentityScheme {
 }
 
 /// Backup repo configuration
-#[derive(Eq, Clone, Default, Debug, PartialEq)]
+#[derive(Eq, Clone, Default, Debug, PartialEq, Serialize)]
 pub struct BackupRepoConfig {
     /// Name of the repo that's a "source" of the backup
     /// i.e. what we are actually backing up
@@ -256,14 +266,14 @@ pub struct BackupRepoConfig {
 }
 
 /// Configuration for repo_client module
-#[derive(Eq, Copy, Clone, Default, Debug, PartialEq)]
+#[derive(Eq, Copy, Clone, Default, Debug, PartialEq, Serialize)]
 pub struct RepoClientKnobs {
     /// Return shorter file history in getpack call
     pub allow_short_getpack_history: bool,
 }
 
 /// Config for derived data
-#[derive(Eq, Clone, Default, Debug, PartialEq)]
+#[derive(Eq, Clone, Default, Debug, PartialEq, Serialize)]
 pub struct DerivedDataConfig {
     /// Name of scuba table where all derivation will be logged to
     pub scuba_table: Option<String>,
@@ -306,7 +316,7 @@ impl DerivedDataConfig {
 }
 
 /// Config for derived data types
-#[derive(Eq, Clone, Default, Debug, PartialEq)]
+#[derive(Eq, Clone, Default, Debug, PartialEq, Serialize)]
 pub struct DerivedDataTypesConfig {
     /// The configured types.
     pub types: HashSet<String>,
@@ -337,7 +347,7 @@ pub struct DerivedDataTypesConfig {
 }
 
 /// What type of unode derived data to generate
-#[derive(Eq, Clone, Copy, Debug, PartialEq)]
+#[derive(Eq, Clone, Copy, Debug, PartialEq, Serialize)]
 pub enum UnodeVersion {
     /// Unodes v1
     V1,
@@ -352,7 +362,7 @@ impl Default for UnodeVersion {
 }
 
 /// What type of blame derived data to generate
-#[derive(Eq, Clone, Copy, Debug, PartialEq)]
+#[derive(Eq, Clone, Copy, Debug, PartialEq, Serialize)]
 pub enum BlameVersion {
     /// Blame v1
     V1,
@@ -373,7 +383,7 @@ impl RepoConfig {
     }
 }
 
-#[derive(Eq, Copy, Clone, Debug, PartialEq, Deserialize)]
+#[derive(Eq, Copy, Clone, Debug, PartialEq, Deserialize, Serialize)]
 /// Should the redaction verification be enabled?
 pub enum Redaction {
     /// Redacted files cannot be accessed
@@ -388,7 +398,7 @@ impl Default for Redaction {
     }
 }
 
-#[derive(Clone, PartialEq, Eq, Debug)]
+#[derive(Clone, PartialEq, Eq, Debug, Serialize)]
 /// Is the repo read-only?
 pub enum RepoReadOnly {
     /// This repo is read-only and should not accept pushes or other writes
@@ -404,7 +414,7 @@ impl Default for RepoReadOnly {
 }
 
 /// Configuration of warming up the Mononoke cache. This warmup happens on startup
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
 pub struct CacheWarmupParams {
     /// Bookmark to warmup cache for at the startup. If not set then the cache will be cold.
     pub bookmark: BookmarkName,
@@ -416,7 +426,7 @@ pub struct CacheWarmupParams {
 }
 
 /// Configuration for the hook manager
-#[derive(Debug, Clone, Eq, PartialEq, Deserialize, Default)]
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize, Serialize, Default)]
 pub struct HookManagerParams {
     /// Wether to disable the acl checker or not (intended for testing purposes)
     pub disable_acl_checker: bool,
@@ -427,7 +437,7 @@ pub struct HookManagerParams {
 }
 
 /// Configuration might be done for a single bookmark or for all bookmarks matching a regex
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
 pub enum BookmarkOrRegex {
     /// Matches a single bookmark
     Bookmark(BookmarkName),
@@ -458,7 +468,7 @@ impl From<Regex> for BookmarkOrRegex {
 }
 
 /// Configuration for a bookmark
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
 pub struct BookmarkParams {
     /// The bookmark
     pub bookmark: BookmarkOrRegex,
@@ -489,7 +499,7 @@ pub struct BookmarkParams {
 }
 
 /// The type of the hook
-#[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize, Serialize)]
 pub enum HookType {
     /// A hook that runs on the whole changeset
     PerChangeset,
@@ -510,7 +520,7 @@ impl FromStr for HookType {
 }
 
 /// Hook bypass
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
 pub struct HookBypass {
     /// Bypass that checks that a string is in the commit message
     commit_message_bypass: Option<String>,
@@ -561,7 +571,7 @@ impl HookBypass {
 }
 
 /// Configs that are being passed to the hook during runtime
-#[derive(Debug, Default, Clone, Eq, PartialEq)]
+#[derive(Debug, Default, Clone, Eq, PartialEq, Serialize)]
 pub struct HookConfig {
     /// An optional way to bypass a hook
     pub bypass: Option<HookBypass>,
@@ -580,7 +590,7 @@ pub struct HookConfig {
 }
 
 /// Configuration for a hook
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
 pub struct HookParams {
     /// The name of the hook
     pub name: String,
@@ -589,7 +599,7 @@ pub struct HookParams {
 }
 
 /// Push configuration options
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
 pub struct PushParams {
     /// Whether normal non-pushrebase pushes are allowed
     pub pure_push_allowed: bool,
@@ -607,7 +617,7 @@ impl Default for PushParams {
 }
 
 /// Flags for the pushrebase inner loop
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
 pub struct PushrebaseFlags {
     /// Update dates of rebased commits
     pub rewritedates: bool,
@@ -636,7 +646,7 @@ impl Default for PushrebaseFlags {
     }
 }
 
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
 /// Either an SMC tier or a host/port pair
 pub enum Address {
     /// An SMC tier
@@ -645,7 +655,7 @@ pub enum Address {
     HostPort(String),
 }
 
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
 /// How to do pushrebase on Mononoke
 pub enum PushrebaseRemoteMode {
     /// Do pushrebase in the same process
@@ -661,7 +671,7 @@ pub enum PushrebaseRemoteMode {
 }
 
 /// Pushrebase configuration options
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
 pub struct PushrebaseParams {
     /// Pushrebase processing flags
     pub flags: PushrebaseFlags,
@@ -701,7 +711,7 @@ impl Default for PushrebaseParams {
 }
 
 /// LFS configuration options
-#[derive(Debug, Default, Clone, Eq, PartialEq)]
+#[derive(Debug, Default, Clone, Eq, PartialEq, Serialize)]
 pub struct LfsParams {
     /// threshold in bytes, If None, Lfs is disabled
     pub threshold: Option<u64>,
@@ -713,7 +723,7 @@ pub struct LfsParams {
 
 /// Id used to discriminate diffirent underlying blobstore instances
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Ord, PartialOrd, Deserialize)]
-#[derive(From, Into, mysql::OptTryFromRowField)]
+#[derive(From, Into, Serialize, mysql::OptTryFromRowField)]
 pub struct BlobstoreId(u64);
 sql::proxy_conv_ir!(BlobstoreId, ParseIr<u64>, u64);
 
@@ -737,7 +747,7 @@ impl From<BlobstoreId> for ScubaValue {
 }
 
 /// Id used to identify storage configuration for a multiplexed blobstore.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Ord, PartialOrd)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Ord, PartialOrd, Serialize)]
 #[derive(From, Into, mysql::OptTryFromRowField)]
 pub struct MultiplexId(i32);
 sql::proxy_conv_ir!(MultiplexId, ParseIr<i32>, i32);
@@ -766,7 +776,7 @@ impl From<MultiplexId> for ScubaValue {
 /// can be broadly classified as "local" and "remote". "Local" is primarily for testing, and is
 /// only suitable for single hosts. "Remote" is durable storage which can be shared by multiple
 /// BlobRepo instances on different hosts.
-#[derive(Debug, Default, Clone, Eq, PartialEq, Hash)]
+#[derive(Debug, Default, Clone, Eq, PartialEq, Hash, Serialize)]
 pub struct StorageConfig {
     /// Blobstores. If the blobstore has a BlobstoreId then it can be used as a component of
     /// a Multiplexed blobstore.
@@ -780,7 +790,7 @@ pub struct StorageConfig {
 
 /// Whether we should read from this blobstore normally in a Multiplex,
 /// or only read from it in Scrub or when it's our last chance to find the blob
-#[derive(Debug, Clone, Copy, Eq, PartialEq, Deserialize, Hash)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Deserialize, Serialize, Hash)]
 pub enum MultiplexedStoreType {
     /// Normal operation, no special treatment
     Normal,
@@ -789,7 +799,7 @@ pub enum MultiplexedStoreType {
 }
 
 /// What format should data be in either Raw or a compressed form with compression options like level
-#[derive(Debug, Clone, Copy, Eq, PartialEq, Deserialize, Hash)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Deserialize, Serialize, Hash)]
 pub enum PackFormat {
     /// Uncompressed data is written by put
     Raw,
@@ -804,14 +814,14 @@ impl Default for PackFormat {
 }
 
 /// Configuration for packblob
-#[derive(Debug, Clone, Copy, Eq, PartialEq, Deserialize, Hash)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Deserialize, Serialize, Hash)]
 pub struct PackConfig {
     /// What format should put write in, either Raw or a compressed form.
     pub put_format: PackFormat,
 }
 
 /// Configuration for a blobstore
-#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize)]
 pub enum BlobConfig {
     /// Administratively disabled blobstore
     Disabled,
@@ -968,21 +978,21 @@ impl Default for BlobConfig {
 }
 
 /// Configuration for a local SQLite database
-#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize)]
 pub struct LocalDatabaseConfig {
     /// Path to the directory containing the SQLite databases
     pub path: PathBuf,
 }
 
 /// Configuration for a remote MySQL database
-#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize)]
 pub struct RemoteDatabaseConfig {
     /// SQL database to connect to
     pub db_address: String,
 }
 
 /// Configuration for a sharded remote MySQL database
-#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize)]
 pub struct ShardedRemoteDatabaseConfig {
     /// SQL database shard map to connect to
     pub shard_map: String,
@@ -991,7 +1001,7 @@ pub struct ShardedRemoteDatabaseConfig {
 }
 
 /// Configuration for a potentially sharded remote MySQL database
-#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize)]
 pub enum ShardableRemoteDatabaseConfig {
     /// Database is not sharded.
     Unsharded(RemoteDatabaseConfig),
@@ -1000,7 +1010,7 @@ pub enum ShardableRemoteDatabaseConfig {
 }
 
 /// Configuration for a single database
-#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize)]
 pub enum DatabaseConfig {
     /// Local SQLite database
     Local(LocalDatabaseConfig),
@@ -1019,7 +1029,7 @@ impl DatabaseConfig {
 }
 
 /// Configuration for a sharded database
-#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize)]
 pub enum ShardedDatabaseConfig {
     /// Local SQLite database
     Local(LocalDatabaseConfig),
@@ -1028,7 +1038,7 @@ pub enum ShardedDatabaseConfig {
 }
 
 /// Configuration for the Metadata database when it is remote.
-#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize)]
 pub struct RemoteMetadataDatabaseConfig {
     /// Database for the primary metadata.
     pub primary: RemoteDatabaseConfig,
@@ -1041,7 +1051,7 @@ pub struct RemoteMetadataDatabaseConfig {
 }
 
 /// Configuration for the Metadata database
-#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize)]
 pub enum MetadataDatabaseConfig {
     /// Local SQLite database
     Local(LocalDatabaseConfig),
@@ -1077,7 +1087,7 @@ impl MetadataDatabaseConfig {
 
 /// Enum configuration representing the possible modes
 /// of deletion for expired bubbles.
-#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize)]
 pub enum BubbleDeletionMode {
     /// No marking or deletion
     Disabled,
@@ -1095,7 +1105,7 @@ impl Default for BubbleDeletionMode {
 
 /// Configuration for the ephemeral blobstore, which stores
 /// blobs for ephemeral changesets and snapshots.
-#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize)]
 pub struct EphemeralBlobstoreConfig {
     /// The configuration of the blobstore where ephemeral blobs
     /// are stored.
@@ -1117,7 +1127,7 @@ pub struct EphemeralBlobstoreConfig {
 }
 
 /// Regex for valid branches that Infinite Pushes can be directed to.
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
 pub struct InfinitepushNamespace(ComparableRegex);
 
 impl InfinitepushNamespace {
@@ -1141,7 +1151,7 @@ impl InfinitepushNamespace {
 /// while still providing a namespace. Doing so will prevent regular pushes to the namespace, as
 /// well as allow the creation of Infinitepush scratchbookmarks through e.g. replicating them from
 /// Mercurial.
-#[derive(Debug, Clone, Eq, PartialEq, Default)]
+#[derive(Debug, Clone, Eq, PartialEq, Default, Serialize)]
 pub struct InfinitepushParams {
     /// Whether infinite push bundles are allowed on this server. If false, all infinitepush
     /// bundles will be rejected.
@@ -1159,7 +1169,7 @@ pub struct InfinitepushParams {
 }
 
 /// Filestore configuration.
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
 pub struct FilestoreParams {
     /// Chunk size for the Filestore, in bytes.
     pub chunk_size: u64,
@@ -1273,7 +1283,7 @@ pub struct SmallRepoPermanentConfig {
 }
 
 /// Source Control Service options
-#[derive(Debug, Clone, Eq, PartialEq, Default)]
+#[derive(Debug, Clone, Eq, PartialEq, Default, Serialize)]
 pub struct SourceControlServiceParams {
     /// Whether writes are permitted.
     pub permit_writes: bool,
@@ -1374,12 +1384,13 @@ impl SourceControlServiceParams {
 }
 
 /// Restrictions on writes for services.
-#[derive(Debug, Default, Clone, Eq, PartialEq)]
+#[derive(Debug, Default, Clone, Eq, PartialEq, Serialize)]
 pub struct ServiceWriteRestrictions {
     /// The service is permissed to call these methods
     pub permitted_methods: HashSet<String>,
 
     /// The service is permitted to modify files with these path prefixes.
+    #[serde(serialize_with = "serialize_prefix_trie")]
     pub permitted_path_prefixes: PrefixTrie,
 
     /// The service is permitted to modify these bookmarks.
@@ -1390,8 +1401,36 @@ pub struct ServiceWriteRestrictions {
     pub permitted_bookmark_regex: Option<ComparableRegex>,
 }
 
+/// Serialize a prefix trie as the sorted list of the path prefixes in it.
+fn serialize_prefix_trie<S>(trie: &PrefixTrie, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    fn prefixes(trie: &PrefixTrie) -> Vec<String> {
+        match trie {
+            PrefixTrie::Included => vec![String::new()],
+            PrefixTrie::Children(children) => children
+                .iter()
+                .flat_map(|(element, child)| {
+                    prefixes(child).into_iter().map(move |rest| {
+                        if rest.is_empty() {
+                            element.to_string()
+                        } else {
+                            format!("{}/{}", element, rest)
+                        }
+                    })
+                })
+                .collect(),
+        }
+    }
+
+    let mut prefixes = prefixes(trie);
+    prefixes.sort();
+    serializer.collect_seq(prefixes)
+}
+
 /// Configuration for health monitoring of the Source Control Service
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
 pub struct SourceControlServiceMonitoring {
     /// Bookmarks, for which we want our services to log
     /// age values to monitoring counters. For example,
@@ -1401,7 +1440,7 @@ pub struct SourceControlServiceMonitoring {
 }
 
 /// Represents the repository name for this repository in Hgsql.
-#[derive(Debug, Default, Clone, Eq, PartialEq)]
+#[derive(Debug, Default, Clone, Eq, PartialEq, Serialize)]
 pub struct HgsqlName(pub String);
 
 impl AsRef<str> for HgsqlName {
@@ -1417,7 +1456,7 @@ impl AsRef<String> for HgsqlName {
 }
 
 /// Represents the repository name for Globalrevs for this repository in Hgsql.
-#[derive(Debug, Default, Clone, Eq, PartialEq)]
+#[derive(Debug, Default, Clone, Eq, PartialEq, Serialize)]
 pub struct HgsqlGlobalrevsName(pub String);
 
 impl AsRef<str> for HgsqlGlobalrevsName {
@@ -1433,7 +1472,7 @@ impl AsRef<String> for HgsqlGlobalrevsName {
 }
 
 /// An unit of configuration for what should be indexed by Segmented Changelog.
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
 pub enum SegmentedChangelogHeadConfig {
     /// All public bookmarks with exceptions.
     AllPublicBookmarksExcept(Vec<BookmarkName>),
@@ -1471,7 +1510,7 @@ impl From<&ChangesetId> for SegmentedChangelogHeadConfig {
 }
 
 /// Configuration for Segmented Changelog.
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
 pub struct SegmentedChangelogConfig {
     /// Signals whether segmented changelog functionality is enabled for the current repository.
     /// This can mean that functionality is disabled to shed load, that the required data is not
@@ -1556,7 +1595,7 @@ impl Default for SegmentedChangelogConfig {
 ///
 /// If the roots and heads lists are both empty then this region covers the
 /// entire repo.
-#[derive(Debug, Default, Clone, Eq, PartialEq)]
+#[derive(Debug, Default, Clone, Eq, PartialEq, Serialize)]
 pub struct AclRegion {
     /// List of roots that begin this region.  Any commit that is a descendant of any
     /// root, including the root itself, will be included in the region.  If this
@@ -1576,7 +1615,7 @@ pub struct AclRegion {
 }
 
 /// ACL region rule consisting of multiple regions and path prefixes
-#[derive(Debug, Default, Clone, Eq, PartialEq)]
+#[derive(Debug, Default, Clone, Eq, PartialEq, Serialize)]
 pub struct AclRegionRule {
     /// The name of this region rule.  This is used in error messages and diagnostics.
     pub name: String,
@@ -1590,14 +1629,14 @@ pub struct AclRegionRule {
 /// Describe ACL Regions for a repository.
 ///
 /// This is a set of rules which define regions of the repository (commits and paths)
-#[derive(Debug, Default, Clone, Eq, PartialEq)]
+#[derive(Debug, Default, Clone, Eq, PartialEq, Serialize)]
 pub struct AclRegionConfig {
     /// List of rules that grant access to regions of the repo.
     pub allow_rules: Vec<AclRegionRule>,
 }
 
 /// Walker parameters that are specific to type of job and repo.
-#[derive(Debug, Default, Clone, Eq, PartialEq)]
+#[derive(Debug, Default, Clone, Eq, PartialEq, Serialize)]
 pub struct WalkerJobParams {
     /// Controls max concurrency for MySQL and other dependencies
     pub scheduled_max_concurrency: Option<i64>,
@@ -1612,7 +1651,7 @@ pub struct WalkerJobParams {
     pub error_as_node_data_type: Option<String>,
 }
 
-#[derive(Debug, Copy, clap::ArgEnum, Clone, Eq, PartialEq, Hash)]
+#[derive(Debug, Copy, clap::ArgEnum, Clone, Eq, PartialEq, Hash, Serialize)]
 /// The type of walker jobs deployed in production
 pub enum WalkerJobType {
     /// Invalid value
@@ -1665,7 +1704,7 @@ impl fmt::Display for WalkerJobType {
 }
 
 /// Configuration relevant to walker job.
-#[derive(Debug, Default, Clone, Eq, PartialEq)]
+#[derive(Debug, Default, Clone, Eq, PartialEq, Serialize)]
 pub struct WalkerConfig {
     /// Determines if the walker should scrub blobs.
     pub scrub_enabled: bool,
@@ -1676,7 +1715,7 @@ pub struct WalkerConfig {
 }
 
 /// Configuration relevant to cross-repo commit validation
-#[derive(Debug, Default, Clone, Eq, PartialEq)]
+#[derive(Debug, Default, Clone, Eq, PartialEq, Serialize)]
 pub struct CrossRepoCommitValidation {
     /// A set of bookmarks whose changelog entries are deemed to be valid
     /// Commits that are only found via the changelog for this named bookmark
@@ -1685,7 +1724,7 @@ pub struct CrossRepoCommitValidation {
 }
 
 /// Configuration for sparse profile monitoring
-#[derive(Debug, Default, Clone, Eq, PartialEq)]
+#[derive(Debug, Default, Clone, Eq, PartialEq, Serialize)]
 pub struct SparseProfilesConfig {
     /// Location where sparse profiles are stored within the repo
     pub sparse_profiles_location: String,
@@ -1699,7 +1738,7 @@ pub struct SparseProfilesConfig {
 
 /// Repo-specific configuration parameters for hg sync job
 /// for a specific job variant
-#[derive(Debug, Default, Clone, Eq, PartialEq)]
+#[derive(Debug, Default, Clone, Eq, PartialEq, Serialize)]
 pub struct HgSyncConfig {
     /// Remote path to hg repo to replay to
     pub hg_repo_ssh_path: String,
@@ -1712,7 +1751,7 @@ pub struct HgSyncConfig {
 }
 
 /// Destination for telemetry logging.
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
 pub enum LoggingDestination {
     /// Logs should be sent to the default logger for this type.
     Logger,
@@ -1724,7 +1763,7 @@ pub enum LoggingDestination {
 }
 
 /// Configuration for logging updates to the repo to external telemetry
-#[derive(Debug, Default, Clone, Eq, PartialEq)]
+#[derive(Debug, Default, Clone, Eq, PartialEq, Serialize)]
 pub struct UpdateLoggingConfig {
     /// Destination where bookmark updates are logged to
     pub bookmark_logging_destination: Option<LoggingDestination>,
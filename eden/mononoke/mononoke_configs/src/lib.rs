@@ -8,6 +8,7 @@
 #![feature(arc_unwrap_or_clone)]
 
 use std::path::Path;
+use std::path::PathBuf;
 use std::sync::Arc;
 
 use anyhow::Result;
@@ -40,6 +41,8 @@ pub struct MononokeConfigs {
     update_receivers: Swappable<Vec<Arc<dyn ConfigUpdateReceiver>>>,
     maybe_config_updater: Option<JoinHandle<()>>,
     maybe_config_handle: Option<ConfigHandle<RawRepoConfigs>>,
+    config_path: PathBuf,
+    config_store: ConfigStore,
 }
 
 impl MononokeConfigs {
@@ -80,9 +83,30 @@ impl MononokeConfigs {
             update_receivers,
             maybe_config_updater,
             maybe_config_handle,
+            config_path: config_path.as_ref().to_path_buf(),
+            config_store: config_store.clone(),
         })
     }
 
+    /// Re-read the configs from the underlying configuration store and notify the registered
+    /// ConfigUpdateReceivers, eg: to pick up changes to a static source, which isn't watched for
+    /// updates. Returns the repo configs from before the reload.
+    pub async fn reload(&self) -> Result<Arc<RepoConfigs>> {
+        let storage_configs =
+            metaconfig_parser::load_storage_configs(&self.config_path, &self.config_store)?;
+        let repo_configs =
+            metaconfig_parser::load_repo_configs(&self.config_path, &self.config_store)?;
+        let old_repo_configs = self.repo_configs.swap(Arc::new(repo_configs));
+        self.storage_configs.store(Arc::new(storage_configs));
+        notify_update_receivers(
+            &self.update_receivers,
+            self.repo_configs(),
+            self.storage_configs(),
+        )
+        .await?;
+        Ok(old_repo_configs)
+    }
+
     /// The latest repo configs fetched from the underlying configuration store.
     pub fn repo_configs(&self) -> Arc<RepoConfigs> {
         // Load full since there could be lots of calls to repo_configs.
@@ -154,15 +178,12 @@ async fn watch_and_update(
                         let new_storage_configs = Arc::new(new_storage_configs);
                         repo_configs.store(new_repo_configs.clone());
                         storage_configs.store(new_storage_configs.clone());
-                        let receivers = update_receivers.load();
-                        let update_tasks = receivers.iter().map(|receiver| {
-                            receiver
-                                .apply_update(new_repo_configs.clone(), new_storage_configs.clone())
-                        });
-                        if let Err(e) = join_all(update_tasks)
-                            .await
-                            .into_iter()
-                            .collect::<Result<Vec<_>>>()
+                        if let Err(e) = notify_update_receivers(
+                            &update_receivers,
+                            new_repo_configs,
+                            new_storage_configs,
+                        )
+                        .await
                         {
                             error!(
                                 logger,
@@ -193,6 +214,22 @@ async fn watch_and_update(
     }
 }
 
+async fn notify_update_receivers(
+    update_receivers: &Swappable<Vec<Arc<dyn ConfigUpdateReceiver>>>,
+    repo_configs: Arc<RepoConfigs>,
+    storage_configs: Arc<StorageConfigs>,
+) -> Result<()> {
+    let receivers = update_receivers.load();
+    let update_tasks = receivers
+        .iter()
+        .map(|receiver| receiver.apply_update(repo_configs.clone(), storage_configs.clone()));
+    join_all(update_tasks)
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>>>()?;
+    Ok(())
+}
+
 /// Trait defining methods related to config update notification. A struct implementing
 /// this trait can be configured to receive the most updated config value everytime the
 /// underlying config changes.
@@ -8,7 +8,10 @@
 use std::fs::File;
 use std::io;
 use std::io::Write;
+use std::net::Ipv4Addr;
 use std::net::SocketAddr;
+use std::os::unix::io::FromRawFd;
+use std::os::unix::io::RawFd;
 use std::path::PathBuf;
 use std::sync::atomic::AtomicBool;
 use std::sync::atomic::AtomicUsize;
@@ -19,6 +22,7 @@ use std::time::Duration;
 use anyhow::Context;
 use anyhow::Error;
 use anyhow::Result;
+use arc_swap::ArcSwap;
 use bytes::Bytes;
 use cached_config::ConfigStore;
 use cmdlib::monitoring::ReadyFlagService;
@@ -27,6 +31,7 @@ use edenapi_service::EdenApi;
 use failure_ext::SlogKVError;
 use fbinit::FacebookInit;
 use futures::channel::oneshot;
+use futures::future::try_join_all;
 use futures::future::Future;
 use futures::select_biased;
 use futures_01_ext::BoxStream;
@@ -47,6 +52,9 @@ use metadata::Metadata;
 use mononoke_api::Mononoke;
 use openssl::ssl::Ssl;
 use openssl::ssl::SslAcceptor;
+use openssl::ssl::SslRef;
+use openssl::ssl::SslVerifyMode;
+use openssl::x509::X509VerifyResult;
 use permission_checker::AclProvider;
 use permission_checker::MononokeIdentity;
 use permission_checker::MononokeIdentitySet;
@@ -60,6 +68,12 @@ use slog::error;
 use slog::info;
 use slog::warn;
 use slog::Logger;
+use socket2::Domain;
+use socket2::Protocol;
+use socket2::SockRef;
+use socket2::Socket;
+use socket2::TcpKeepalive;
+use socket2::Type;
 use sshrelay::IoStream;
 use sshrelay::SshDecoder;
 use sshrelay::SshEncoder;
@@ -70,6 +84,10 @@ use tokio::io::AsyncRead;
 use tokio::io::AsyncWrite;
 use tokio::net::TcpListener;
 use tokio::net::TcpStream;
+use tokio::net::UnixListener;
+use tokio::net::UnixStream;
+use tokio::sync::OwnedSemaphorePermit;
+use tokio::sync::Semaphore;
 use tokio::task::JoinHandle;
 use tokio_openssl::SslStream;
 use tokio_util::codec::FramedRead;
@@ -77,6 +95,9 @@ use tokio_util::codec::FramedWrite;
 
 use crate::errors::ErrorKind;
 use crate::http_service::MononokeHttpService;
+use crate::idle_timeout::IdleTimeoutStream;
+use crate::permit_stream::PermitStream;
+use crate::proxy_protocol::read_proxy_header;
 use crate::request_handler::create_conn_logger;
 use crate::request_handler::request_handler;
 use crate::wireproto_sink::WireprotoSink;
@@ -84,6 +105,16 @@ use crate::wireproto_sink::WireprotoSink;
 define_stats! {
     prefix = "mononoke.connection_acceptor";
     http_accepted: timeseries(Sum),
+    open_connections: singleton_counter(),
+    connections_accepted: timeseries(Sum),
+    connections_rejected: timeseries(Sum),
+    connections_queued: timeseries(Sum),
+    connections_idle_timeout: timeseries(Sum),
+    tls_handshake_failures: dynamic_timeseries(
+        "tls_handshake_failures.{}",
+        (reason: &'static str);
+        Sum
+    ),
 }
 
 pub trait MononokeStream: AsyncRead + AsyncWrite + Unpin + Send + Sync + 'static {}
@@ -108,14 +139,202 @@ pub async fn wait_for_connections_closed(logger: &Logger) {
     }
 }
 
+/// Options for the TCP sockets we listen on.
+#[derive(Clone, Debug, Default)]
+pub struct TcpOptions {
+    /// Whether IPv6 sockets should refuse IPv4-mapped connections. The OS default varies, so it
+    /// is left untouched if unset.
+    pub ipv6_only: Option<bool>,
+    /// Set SO_REUSEPORT, so that another process can listen on the same address at the same time.
+    pub reuse_port: bool,
+    /// How many more times to try binding if it fails, e.g. because the previous instance has not
+    /// released the address yet.
+    pub bind_retries: u32,
+    /// How long to wait before the first retry. The wait doubles with every retry.
+    pub bind_retry_interval: Duration,
+}
+
+/// TCP keepalive settings for accepted connections. Keepalive is enabled if any is set, and the
+/// unset ones keep the OS defaults.
+#[derive(Clone, Debug, Default)]
+pub struct KeepaliveOptions {
+    /// How long a connection must be idle before probes are sent.
+    pub idle: Option<Duration>,
+    /// The time between probes.
+    pub interval: Option<Duration>,
+    /// How many unanswered probes it takes to drop the connection.
+    pub probes: Option<u32>,
+}
+
+impl KeepaliveOptions {
+    fn apply(&self, stream: &TcpStream, logger: &Logger) {
+        if self.idle.is_none() && self.interval.is_none() && self.probes.is_none() {
+            return;
+        }
+        let mut keepalive = TcpKeepalive::new();
+        if let Some(idle) = self.idle {
+            keepalive = keepalive.with_time(idle);
+        }
+        if let Some(interval) = self.interval {
+            keepalive = keepalive.with_interval(interval);
+        }
+        if let Some(probes) = self.probes {
+            keepalive = keepalive.with_retries(probes);
+        }
+        if let Err(e) = SockRef::from(stream).set_tcp_keepalive(&keepalive) {
+            warn!(logger, "Failed to set up TCP keepalive {:?}: {}", self, e);
+        }
+    }
+}
+
+/// A socket to accept connections on.
+#[derive(Clone, Debug)]
+pub enum ListeningSocket {
+    /// A TCP address in `host:port` format. Connections always use TLS. With a `repo`, they can
+    /// only use that repo.
+    Tcp {
+        addr: String,
+        options: TcpOptions,
+        repo: Option<String>,
+    },
+    /// The path of a Unix domain socket, replaced if it already exists and removed on shutdown.
+    /// Without TLS, connections are local but unauthenticated, so they have no identities.
+    Uds { path: PathBuf, tls: bool },
+    /// A TCP socket that is already listening, passed by the service manager that started us,
+    /// e.g. for systemd socket activation. Connections always use TLS.
+    Inherited(RawFd),
+}
+
+enum Listener {
+    Tcp {
+        listener: TcpListener,
+        repo: Option<String>,
+    },
+    Uds {
+        listener: UnixListener,
+        path: PathBuf,
+        tls: bool,
+    },
+}
+
+impl ListeningSocket {
+    async fn bind(&self, logger: &Logger) -> Result<Listener> {
+        match self {
+            Self::Tcp {
+                addr,
+                options,
+                repo,
+            } => {
+                let sockaddr: SocketAddr = addr
+                    .parse()
+                    .with_context(|| format!("could not parse '{}'", addr))?;
+                let listener = bind_tcp_with_retries(sockaddr, options, logger)
+                    .await
+                    .with_context(|| format!("could not bind mononoke on '{}'", addr))?;
+                Ok(Listener::Tcp {
+                    listener,
+                    repo: repo.clone(),
+                })
+            }
+            Self::Uds { path, tls } => {
+                match std::fs::remove_file(path) {
+                    Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                        return Err(e).with_context(|| {
+                            format!("could not remove existing socket '{}'", path.display())
+                        });
+                    }
+                    _ => {}
+                }
+                let listener = UnixListener::bind(path)
+                    .with_context(|| format!("could not bind mononoke on '{}'", path.display()))?;
+                Ok(Listener::Uds {
+                    listener,
+                    path: path.clone(),
+                    tls: *tls,
+                })
+            }
+            Self::Inherited(fd) => {
+                // Safety: the service manager handed this socket over to us, and it is only
+                // bound once.
+                let listener = unsafe { std::net::TcpListener::from_raw_fd(*fd) };
+                listener
+                    .set_nonblocking(true)
+                    .with_context(|| format!("could not use inherited socket {}", fd))?;
+                Ok(Listener::Tcp {
+                    listener: TcpListener::from_std(listener)?,
+                    repo: None,
+                })
+            }
+        }
+    }
+}
+
+async fn bind_tcp_with_retries(
+    addr: SocketAddr,
+    options: &TcpOptions,
+    logger: &Logger,
+) -> Result<TcpListener> {
+    let mut interval = options.bind_retry_interval;
+    let mut attempt = 0;
+    loop {
+        match bind_tcp(addr, options) {
+            Err(e) if attempt < options.bind_retries => {
+                attempt += 1;
+                warn!(
+                    logger,
+                    "Failed to bind {}, retrying in {:?} ({}/{}): {:#}",
+                    addr,
+                    interval,
+                    attempt,
+                    options.bind_retries,
+                    e
+                );
+                tokio::time::sleep(interval).await;
+                interval = interval.saturating_mul(2);
+            }
+            res => return res,
+        }
+    }
+}
+
+/// Bind like `TcpListener::bind` does, but with control over the socket options.
+fn bind_tcp(addr: SocketAddr, options: &TcpOptions) -> Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    if let (true, Some(ipv6_only)) = (addr.is_ipv6(), options.ipv6_only) {
+        socket
+            .set_only_v6(ipv6_only)
+            .context("could not set IPV6_V6ONLY")?;
+    }
+    socket.set_reuse_address(true)?;
+    if options.reuse_port {
+        set_reuse_port(&socket)?;
+    }
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    Ok(TcpListener::from_std(socket.into())?)
+}
+
+#[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos"))))]
+fn set_reuse_port(socket: &Socket) -> Result<()> {
+    socket
+        .set_reuse_port(true)
+        .context("could not set SO_REUSEPORT")
+}
+
+#[cfg(not(all(unix, not(any(target_os = "solaris", target_os = "illumos")))))]
+fn set_reuse_port(_socket: &Socket) -> Result<()> {
+    anyhow::bail!("SO_REUSEPORT is not supported on this platform")
+}
+
 pub async fn connection_acceptor(
     fb: FacebookInit,
     common_config: CommonConfig,
-    sockname: String,
+    sockets: Vec<ListeningSocket>,
     service: ReadyFlagService,
     root_log: Logger,
     mononoke: Arc<Mononoke>,
-    tls_acceptor: SslAcceptor,
+    tls_acceptor: Arc<ArcSwap<SslAcceptor>>,
     terminate_process: oneshot::Receiver<()>,
     rate_limiter: Option<RateLimitEnvironment>,
     scribe: Scribe,
@@ -127,18 +346,17 @@ pub async fn connection_acceptor(
     bound_addr_path: Option<PathBuf>,
     acl_provider: &dyn AclProvider,
     readonly: bool,
+    expect_proxy_protocol: bool,
+    max_connections: Option<usize>,
+    connection_idle_timeout: Option<Duration>,
+    keepalive: KeepaliveOptions,
 ) -> Result<()> {
     let enable_http_control_api = common_config.enable_http_control_api;
 
     let security_checker = ConnectionSecurityChecker::new(acl_provider, &common_config).await?;
-    let addr: SocketAddr = sockname
-        .parse()
-        .with_context(|| format!("could not parse '{}'", sockname))?;
-    let listener = TcpListener::bind(&addr)
-        .await
-        .with_context(|| format!("could not bind mononoke on '{}'", sockname))?;
+    let listeners = try_join_all(sockets.iter().map(|socket| socket.bind(&root_log))).await?;
 
-    let mut terminate_process = terminate_process.fuse();
+    let terminate_process = terminate_process.shared();
 
     let qps = match cslb_config {
         Some(config) => Some(Arc::new(
@@ -150,11 +368,17 @@ pub async fn connection_acceptor(
     // Now that we are listening and ready to accept connections, report that we are alive.
     service.set_ready();
 
-    let bound_addr = listener.local_addr()?.to_string();
-    debug!(root_log, "server is listening on {}", bound_addr);
+    let mut bound_addrs = vec![];
+    for listener in &listeners {
+        if let Listener::Tcp { listener, .. } = listener {
+            let bound_addr = listener.local_addr()?.to_string();
+            debug!(root_log, "server is listening on {}", bound_addr);
+            bound_addrs.push(bound_addr);
+        }
+    }
 
-    // Write out the bound address if requested, this is helpful in tests when using automatic binding with :0
-    if let Some(bound_addr_path) = bound_addr_path {
+    // Write out the first bound address if requested, this is helpful in tests when using automatic binding with :0
+    if let (Some(bound_addr_path), Some(bound_addr)) = (bound_addr_path, bound_addrs.first()) {
         let mut writer = File::create(bound_addr_path)?;
         writer.write_all(bound_addr.as_bytes())?;
         writer.write_all(b"\n")?;
@@ -177,32 +401,197 @@ pub async fn connection_acceptor(
         wireproto_scuba,
         common_config,
         readonly,
+        expect_proxy_protocol,
+        connection_limit: max_connections.map(|max| Arc::new(Semaphore::new(max))),
+        connection_idle_timeout,
+        keepalive,
     });
 
+    try_join_all(listeners.into_iter().map(|listener| {
+        accept_loop(
+            listener,
+            acceptor.clone(),
+            terminate_process.clone().map(|_| ()),
+        )
+    }))
+    .await?;
+    Ok(())
+}
+
+/// A connection accepted on a `Listener`, along with how the listener serves it.
+enum Accepted {
+    Tcp {
+        stream: TcpStream,
+        addr: SocketAddr,
+        repo: Option<String>,
+    },
+    Uds {
+        stream: UnixStream,
+        tls: bool,
+    },
+}
+
+impl Listener {
+    async fn accept(&self) -> io::Result<Accepted> {
+        match self {
+            Listener::Tcp { listener, repo } => {
+                let (stream, addr) = listener.accept().await?;
+                Ok(Accepted::Tcp {
+                    stream,
+                    addr,
+                    repo: repo.clone(),
+                })
+            }
+            Listener::Uds { listener, tls, .. } => {
+                let (stream, _) = listener.accept().await?;
+                Ok(Accepted::Uds { stream, tls: *tls })
+            }
+        }
+    }
+}
+
+/// Accept connections on `listener` until `terminate_process` completes, and pass each of them
+/// to `handle` along with a permit of `connection_limit` to hold while it is open, if any.
+///
+/// Permits are only acquired for connections that were accepted, so that listeners without any
+/// don't hold on to one. While there are none left, the accepted connection waits without being
+/// handled and the next ones queue in the listen backlog, until some of the open ones close.
+async fn accept_with_limit(
+    listener: &Listener,
+    connection_limit: Option<&Arc<Semaphore>>,
+    terminate_process: impl Future<Output = ()>,
+    logger: &Logger,
+    mut handle: impl FnMut(Accepted, Option<OwnedSemaphorePermit>),
+) -> Result<()> {
+    let mut terminate_process = Box::pin(terminate_process.fuse());
     loop {
-        select_biased! {
-            _ = terminate_process => {
-                debug!(root_log, "Received shutdown handler, stop accepting connections...");
-                return Ok(());
-            },
-            sock_tuple = listener.accept().fuse() => match sock_tuple {
-                Ok((stream, addr)) => {
-                    let conn = PendingConnection { acceptor: acceptor.clone(), addr };
-                    let task = handle_connection(conn.clone(), stream);
-                    conn.spawn_task(task, "Failed to handle_connection");
-                }
-                Err(err) => {
-                    error!(root_log, "{}", err.to_string(); SlogKVError(Error::from(err)));
+        let accepted = select_biased! {
+            _ = terminate_process => break,
+            accepted = listener.accept().fuse() => accepted,
+        };
+        let accepted = match accepted {
+            Ok(accepted) => accepted,
+            Err(err) => {
+                error!(logger, "{}", err.to_string(); SlogKVError(Error::from(err)));
+                continue;
+            }
+        };
+
+        let permit = match connection_limit {
+            Some(limit) => Some(match limit.clone().try_acquire_owned() {
+                Ok(permit) => permit,
+                Err(_) => {
+                    STATS::connections_queued.add_value(1);
+                    warn!(
+                        logger,
+                        "Too many open connections, waiting for some to close before accepting more"
+                    );
+                    select_biased! {
+                        _ = terminate_process => break,
+                        permit = limit.clone().acquire_owned().fuse() => permit?,
+                    }
                 }
-            },
+            }),
+            None => None,
         };
+        handle(accepted, permit);
+    }
+    Ok(())
+}
+
+async fn accept_loop(
+    listener: Listener,
+    acceptor: Arc<Acceptor>,
+    terminate_process: impl Future<Output = ()>,
+) -> Result<()> {
+    let root_log = &acceptor.logger;
+    accept_with_limit(
+        &listener,
+        acceptor.connection_limit.as_ref(),
+        terminate_process,
+        root_log,
+        |accepted, permit| match accepted {
+            Accepted::Tcp { stream, addr, repo } => {
+                acceptor.keepalive.apply(&stream, root_log);
+                spawn_connection(
+                    &acceptor,
+                    stream,
+                    addr,
+                    true,
+                    acceptor.expect_proxy_protocol,
+                    repo,
+                    permit,
+                );
+            }
+            Accepted::Uds { stream, tls } => {
+                // Peers of a Unix domain socket are local.
+                let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, 0));
+                spawn_connection(&acceptor, stream, addr, tls, false, None, permit);
+            }
+        },
+    )
+    .await?;
+
+    debug!(
+        root_log,
+        "Received shutdown handler, stop accepting connections..."
+    );
+    if let Listener::Uds { path, .. } = &listener {
+        if let Err(e) = std::fs::remove_file(path) {
+            warn!(
+                root_log,
+                "Failed to remove socket {}: {}",
+                path.display(),
+                e
+            );
+        }
     }
+    Ok(())
+}
+
+fn spawn_connection<S: MononokeStream>(
+    acceptor: &Arc<Acceptor>,
+    stream: S,
+    addr: SocketAddr,
+    tls: bool,
+    proxy_protocol: bool,
+    repo: Option<String>,
+    permit: Option<OwnedSemaphorePermit>,
+) {
+    STATS::connections_accepted.add_value(1);
+    let conn = PendingConnection {
+        acceptor: acceptor.clone(),
+        addr,
+        repo,
+    };
+    let task = {
+        let conn = conn.clone();
+        async move {
+            // The permit goes along with the stream, even if it is upgraded and handed off.
+            let stream = PermitStream::new(stream, permit);
+            let idle_timeout = match conn.acceptor.connection_idle_timeout {
+                Some(idle_timeout) => idle_timeout,
+                None => return handle_connection(conn, stream, tls, proxy_protocol).await,
+            };
+
+            let logger = conn.acceptor.logger.clone();
+            let stream = IdleTimeoutStream::new(stream, idle_timeout, move || {
+                STATS::connections_idle_timeout.add_value(1);
+                debug!(
+                    logger,
+                    "Closing connection to {} after {:?} without activity", addr, idle_timeout
+                );
+            });
+            handle_connection(conn, stream, tls, proxy_protocol).await
+        }
+    };
+    conn.spawn_task(task, "Failed to handle_connection");
 }
 
 /// Our environment for accepting connections.
 pub struct Acceptor {
     pub fb: FacebookInit,
-    pub tls_acceptor: SslAcceptor,
+    pub tls_acceptor: Arc<ArcSwap<SslAcceptor>>,
     pub mononoke: Arc<Mononoke>,
     pub security_checker: ConnectionSecurityChecker,
     pub rate_limiter: Option<RateLimitEnvironment>,
@@ -217,6 +606,10 @@ pub struct Acceptor {
     pub wireproto_scuba: MononokeScubaSampleBuilder,
     pub common_config: CommonConfig,
     pub readonly: bool,
+    pub expect_proxy_protocol: bool,
+    pub connection_limit: Option<Arc<Semaphore>>,
+    pub connection_idle_timeout: Option<Duration>,
+    pub keepalive: KeepaliveOptions,
 }
 
 /// Details for a socket we've just opened.
@@ -224,6 +617,8 @@ pub struct Acceptor {
 pub struct PendingConnection {
     pub acceptor: Arc<Acceptor>,
     pub addr: SocketAddr,
+    /// The only repo this connection may use, if it came in on a port dedicated to it.
+    pub repo: Option<String>,
 }
 
 /// A connection where we completed the initial TLS handshake.
@@ -235,6 +630,11 @@ pub struct AcceptedConnection {
 }
 
 impl PendingConnection {
+    /// Whether the connection may use `reponame`.
+    pub fn allows_repo(&self, reponame: &str) -> bool {
+        self.repo.as_deref().map_or(true, |repo| repo == reponame)
+    }
+
     /// Spawn a task that is dedicated to this connection. This will block server shutdown, and
     /// also log on error or cancellation.
     pub fn spawn_task(
@@ -244,7 +644,8 @@ impl PendingConnection {
     ) {
         let this = self.clone();
 
-        OPEN_CONNECTIONS.fetch_add(1, Ordering::Relaxed);
+        let conns = OPEN_CONNECTIONS.fetch_add(1, Ordering::Relaxed) + 1;
+        STATS::open_connections.set_value(this.acceptor.fb, conns as i64);
 
         tokio::task::spawn(async move {
             let logger = &this.acceptor.logger;
@@ -258,26 +659,65 @@ impl PendingConnection {
                 error!(logger, "connection_acceptor error: {:#}", e);
             }
 
-            OPEN_CONNECTIONS.fetch_sub(1, Ordering::Relaxed);
+            let conns = OPEN_CONNECTIONS.fetch_sub(1, Ordering::Relaxed) - 1;
+            STATS::open_connections.set_value(this.acceptor.fb, conns as i64);
         });
     }
 }
 
-async fn handle_connection(conn: PendingConnection, sock: TcpStream) -> Result<()> {
-    let ssl = Ssl::new(conn.acceptor.tls_acceptor.context()).context("Error creating Ssl")?;
+async fn handle_connection<S: MononokeStream>(
+    mut conn: PendingConnection,
+    mut sock: S,
+    tls: bool,
+    proxy_protocol: bool,
+) -> Result<()> {
+    if proxy_protocol {
+        // The peer is a load balancer, the client is whoever it says it's proxying for.
+        if let Some(addr) = rejected(
+            read_proxy_header(&mut sock)
+                .await
+                .with_context(|| format!("Invalid PROXY protocol header from {}", conn.addr)),
+        )? {
+            conn.addr = addr;
+        }
+    }
+
+    if !tls {
+        let conn = AcceptedConnection {
+            pending: conn,
+            is_trusted: false,
+            identities: Arc::new(MononokeIdentitySet::new()),
+        };
+        return handle_http(conn, sock, false)
+            .await
+            .context("Failed to handle_http");
+    }
+
+    // Certificates may be swapped at any time, use the ones current at the time of the handshake.
+    let tls_acceptor = conn.acceptor.tls_acceptor.load();
+    let ssl = Ssl::new(tls_acceptor.context()).context("Error creating Ssl")?;
     let ssl_socket = SslStream::new(ssl, sock).context("Error creating SslStream")?;
     let mut ssl_socket = Box::pin(ssl_socket);
 
-    ssl_socket
-        .as_mut()
-        .accept()
-        .await
-        .context("Failed to perform tls handshake")?;
+    let handshake = ssl_socket.as_mut().accept().await;
+    if let Err(e) = &handshake {
+        let reason = handshake_failure_reason(ssl_socket.ssl(), e);
+        STATS::tls_handshake_failures.add_value(1, (reason,));
+    }
+    rejected(handshake.context("Failed to perform tls handshake"))?;
+
+    let http2 = ssl_socket.ssl().selected_alpn_protocol() == Some(alpn::H2_ALPN.as_bytes());
 
-    let identities = match ssl_socket.ssl().peer_certificate() {
+    // Unless the acceptor requires client certificates, clients without one have no identities.
+    let cert_required = tls_acceptor
+        .context()
+        .verify_mode()
+        .contains(SslVerifyMode::FAIL_IF_NO_PEER_CERT);
+    let identities = rejected(match ssl_socket.ssl().peer_certificate() {
         Some(cert) => MononokeIdentity::try_from_x509(&cert),
+        None if !cert_required => Ok(MononokeIdentitySet::new()),
         None => Err(ErrorKind::ConnectionNoClientCertificate.into()),
-    }?;
+    })?;
 
     let is_trusted = conn
         .acceptor
@@ -293,22 +733,60 @@ async fn handle_connection(conn: PendingConnection, sock: TcpStream) -> Result<(
 
     let ssl_socket = QuietShutdownStream::new(ssl_socket);
 
-    handle_http(conn, ssl_socket)
+    handle_http(conn, ssl_socket, http2)
         .await
         .context("Failed to handle_http")?;
 
     Ok(())
 }
 
-async fn handle_http<S: MononokeStream>(conn: AcceptedConnection, stream: S) -> Result<()> {
+/// Count connections we refuse to serve because of what the client sent.
+fn rejected<T>(res: Result<T>) -> Result<T> {
+    if res.is_err() {
+        STATS::connections_rejected.add_value(1);
+    }
+    res
+}
+
+/// A coarse reason for a failed TLS handshake, to tell client rollout problems apart.
+fn handshake_failure_reason(ssl: &SslRef, e: &openssl::ssl::Error) -> &'static str {
+    if ssl.verify_result() != X509VerifyResult::OK {
+        return "cert_rejected";
+    }
+    let reasons = e
+        .ssl_error()
+        .into_iter()
+        .flat_map(|stack| stack.errors())
+        .filter_map(|error| error.reason());
+    for reason in reasons {
+        if reason.contains("certificate") {
+            return "cert_rejected";
+        }
+        if reason.contains("application protocol") {
+            return "alpn_mismatch";
+        }
+    }
+    "protocol_error"
+}
+
+/// Serve HTTP/2 if it was negotiated over ALPN, HTTP/1.1 otherwise. Upgrading to the wireproto
+/// over a websocket is only possible over HTTP/1.1.
+async fn handle_http<S: MononokeStream>(
+    conn: AcceptedConnection,
+    stream: S,
+    http2: bool,
+) -> Result<()> {
     STATS::http_accepted.add_value(1);
 
     let svc = MononokeHttpService::<S>::new(conn);
 
-    // NOTE: We don't select h2 in alpn, so we only expect HTTP/1.1 here.
-    Http::new()
-        .http1_only(true)
-        .serve_connection(stream, svc)
+    let mut http = Http::new();
+    if http2 {
+        http.http2_only(true);
+    } else {
+        http.http1_only(true);
+    }
+    http.serve_connection(stream, svc)
         .with_upgrades()
         .await
         .context("Failed to serve_connection")?;
@@ -541,3 +1019,73 @@ fn split_bytes_in_chunk<E>(blob: Bytes, chunksize: usize) -> impl Stream<Item =
         }
     })
 }
+
+#[cfg(test)]
+mod test {
+    use slog::o;
+    use slog::Discard;
+    use tokio::time::timeout;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_connection_limit_across_listeners() -> Result<()> {
+        let logger = Logger::root(Discard, o!());
+        let socket = ListeningSocket::Tcp {
+            addr: "127.0.0.1:0".to_string(),
+            options: TcpOptions::default(),
+            repo: None,
+        };
+        let listeners = [socket.bind(&logger).await?, socket.bind(&logger).await?];
+        let addrs = listeners
+            .iter()
+            .map(|listener| match listener {
+                Listener::Tcp { listener, .. } => listener.local_addr(),
+                Listener::Uds { .. } => unreachable!(),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let limit = Arc::new(Semaphore::new(1));
+        let (terminate, terminate_process) = oneshot::channel::<()>();
+        let terminate_process = terminate_process.shared();
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        let accept_loops = try_join_all(listeners.iter().enumerate().map(|(index, listener)| {
+            let sender = sender.clone();
+            accept_with_limit(
+                listener,
+                Some(&limit),
+                terminate_process.clone().map(|_| ()),
+                &logger,
+                move |_, permit| sender.send((index, permit)).unwrap(),
+            )
+        }));
+
+        let connect = async {
+            // Idle listeners don't hold permits, whichever listener is connected to first.
+            let _first = TcpStream::connect(addrs[1]).await?;
+            let (index, permit) = timeout(Duration::from_secs(10), receiver.recv())
+                .await?
+                .unwrap();
+            assert_eq!(index, 1);
+
+            // Over the limit, connections wait for the open ones to close.
+            let _second = TcpStream::connect(addrs[0]).await?;
+            assert!(timeout(Duration::from_millis(100), receiver.recv())
+                .await
+                .is_err());
+            drop(permit);
+            let (index, _permit) = timeout(Duration::from_secs(10), receiver.recv())
+                .await?
+                .unwrap();
+            assert_eq!(index, 0);
+
+            terminate.send(()).unwrap();
+            anyhow::Ok(())
+        };
+
+        let (accept_loops, connect) = futures::future::join(accept_loops, connect).await;
+        connect?;
+        accept_loops?;
+        Ok(())
+    }
+}
@@ -221,6 +221,10 @@ where
             .map_err(HttpError::BadRequest)?
             .into_owned();
 
+        if !self.conn.pending.allows_repo(&reponame) {
+            return Err(HttpError::Forbidden);
+        }
+
         let websocket_key = calculate_websocket_accept(req.headers());
 
         let mut builder = Response::builder()
@@ -363,6 +367,15 @@ where
             return Ok(res);
         }
 
+        // EdenAPI paths that are about a repo start with its name.
+        if let Some(reponame) = pq.path().trim_start_matches('/').split('/').next() {
+            if self.acceptor().mononoke.raw_repo(reponame).is_some()
+                && !self.conn.pending.allows_repo(reponame)
+            {
+                return Err(HttpError::Forbidden);
+            }
+        }
+
         let mut uri_parts = req.uri.into_parts();
 
         uri_parts.path_and_query = Some(pq);
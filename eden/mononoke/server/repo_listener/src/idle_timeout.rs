@@ -0,0 +1,276 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::future::Future;
+use std::io::Error;
+use std::io::ErrorKind;
+use std::pin::Pin;
+use std::time::Duration;
+
+use futures::task::Context;
+use futures::task::Poll;
+use pin_project::pin_project;
+use tokio::io::AsyncRead;
+use tokio::io::AsyncWrite;
+use tokio::io::ReadBuf;
+use tokio::time::sleep;
+use tokio::time::Instant;
+use tokio::time::Sleep;
+
+/// A stream that gives up on the connection once no bytes went through it, in either direction,
+/// for `timeout`: reads then see the end of the stream, and writes fail. The timer lives in the
+/// stream, so it keeps running after the stream is upgraded and handed off to another task.
+#[pin_project]
+pub struct IdleTimeoutStream<T> {
+    #[pin]
+    inner: T,
+    timeout: Duration,
+    // Reads and writes may be waited on concurrently, eg: when the stream is split, so each has
+    // its own timer to wake them up.
+    read_sleep: Pin<Box<Sleep>>,
+    write_sleep: Pin<Box<Sleep>>,
+    idle: bool,
+    on_idle: Option<Box<dyn FnOnce() + Send + Sync>>,
+}
+
+impl<T> IdleTimeoutStream<T> {
+    /// `on_idle` is called the first time the stream turns out to be idle.
+    pub fn new(
+        inner: T,
+        timeout: Duration,
+        on_idle: impl FnOnce() + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            inner,
+            timeout,
+            read_sleep: Box::pin(sleep(timeout)),
+            write_sleep: Box::pin(sleep(timeout)),
+            idle: false,
+            on_idle: Some(Box::new(on_idle)),
+        }
+    }
+}
+
+fn record_activity(
+    timeout: Duration,
+    read_sleep: &mut Pin<Box<Sleep>>,
+    write_sleep: &mut Pin<Box<Sleep>>,
+) {
+    let deadline = Instant::now() + timeout;
+    read_sleep.as_mut().reset(deadline);
+    write_sleep.as_mut().reset(deadline);
+}
+
+/// Whether the stream is idle, polling `sleep` to be woken up once it is.
+fn poll_idle(
+    cx: &mut Context,
+    sleep: &mut Pin<Box<Sleep>>,
+    idle: &mut bool,
+    on_idle: &mut Option<Box<dyn FnOnce() + Send + Sync>>,
+) -> bool {
+    if !*idle && sleep.as_mut().poll(cx).is_ready() {
+        *idle = true;
+        if let Some(on_idle) = on_idle.take() {
+            on_idle();
+        }
+    }
+    *idle
+}
+
+impl<T> AsyncRead for IdleTimeoutStream<T>
+where
+    T: AsyncRead,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<(), Error>> {
+        let this = self.project();
+        if *this.idle {
+            return Poll::Ready(Ok(()));
+        }
+        let filled = buf.filled().len();
+        match this.inner.poll_read(cx, buf) {
+            Poll::Pending => {
+                if poll_idle(cx, this.read_sleep, this.idle, this.on_idle) {
+                    Poll::Ready(Ok(()))
+                } else {
+                    Poll::Pending
+                }
+            }
+            res => {
+                if buf.filled().len() > filled {
+                    record_activity(*this.timeout, this.read_sleep, this.write_sleep);
+                }
+                res
+            }
+        }
+    }
+}
+
+impl<T> AsyncWrite for IdleTimeoutStream<T>
+where
+    T: AsyncWrite,
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &[u8],
+    ) -> Poll<Result<usize, Error>> {
+        let this = self.project();
+        if *this.idle {
+            return Poll::Ready(Err(idle_error(*this.timeout)));
+        }
+        match this.inner.poll_write(cx, buf) {
+            Poll::Pending => {
+                if poll_idle(cx, this.write_sleep, this.idle, this.on_idle) {
+                    Poll::Ready(Err(idle_error(*this.timeout)))
+                } else {
+                    Poll::Pending
+                }
+            }
+            res => {
+                if let Poll::Ready(Ok(n)) = res {
+                    if n > 0 {
+                        record_activity(*this.timeout, this.read_sleep, this.write_sleep);
+                    }
+                }
+                res
+            }
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Error>> {
+        let this = self.project();
+        this.inner.poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Error>> {
+        let this = self.project();
+        this.inner.poll_shutdown(cx)
+    }
+}
+
+fn idle_error(timeout: Duration) -> Error {
+    Error::new(
+        ErrorKind::TimedOut,
+        format!("Connection idle for {:?}", timeout),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::AtomicBool;
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+
+    use anyhow::Result;
+    use tokio::io::AsyncReadExt;
+    use tokio::io::AsyncWriteExt;
+    use tokio::io::DuplexStream;
+
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    /// The timer rounds deadlines up to the next millisecond.
+    fn assert_elapsed(start: Instant, expected: Duration) {
+        let elapsed = start.elapsed();
+        assert!(
+            elapsed >= expected && elapsed <= expected + Duration::from_millis(1),
+            "elapsed {:?}, expected {:?}",
+            elapsed,
+            expected
+        );
+    }
+
+    fn idle_timeout_stream(
+        inner: DuplexStream,
+    ) -> (IdleTimeoutStream<DuplexStream>, Arc<AtomicBool>) {
+        let idle = Arc::new(AtomicBool::new(false));
+        let stream = IdleTimeoutStream::new(inner, TIMEOUT, {
+            let idle = idle.clone();
+            move || idle.store(true, Ordering::Relaxed)
+        });
+        (stream, idle)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_idle_after_timeout() -> Result<()> {
+        let start = Instant::now();
+        let (_client, server) = tokio::io::duplex(1024);
+        let (mut stream, idle) = idle_timeout_stream(server);
+
+        // Reads see the end of the stream, the client is still connected.
+        let mut buf = Vec::new();
+        assert_eq!(stream.read_to_end(&mut buf).await?, 0);
+        assert_elapsed(start, TIMEOUT);
+        assert!(idle.load(Ordering::Relaxed));
+
+        let err = stream.write_all(b"late").await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TimedOut);
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_blocked_write_times_out() -> Result<()> {
+        let start = Instant::now();
+        // The client never reads, so the second byte can't be written.
+        let (_client, server) = tokio::io::duplex(1);
+        let (mut stream, idle) = idle_timeout_stream(server);
+
+        let err = stream.write_all(b"ab").await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TimedOut);
+        assert_elapsed(start, TIMEOUT);
+        assert!(idle.load(Ordering::Relaxed));
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_reset_on_read() -> Result<()> {
+        let start = Instant::now();
+        let (mut client, server) = tokio::io::duplex(1024);
+        let (mut stream, idle) = idle_timeout_stream(server);
+
+        let write = async {
+            tokio::time::sleep(Duration::from_secs(6)).await;
+            client.write_all(b"hello").await
+        };
+        let mut buf = Vec::new();
+        let (read, write) = tokio::join!(stream.read_to_end(&mut buf), write);
+        write?;
+        assert_eq!(read?, 5);
+        assert_elapsed(start, Duration::from_secs(6) + TIMEOUT);
+        assert!(idle.load(Ordering::Relaxed));
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_reset_on_write() -> Result<()> {
+        let start = Instant::now();
+        let (mut client, server) = tokio::io::duplex(1024);
+        let (stream, idle) = idle_timeout_stream(server);
+        let (mut rx, mut tx) = tokio::io::split(stream);
+
+        let write = async {
+            tokio::time::sleep(Duration::from_secs(6)).await;
+            tx.write_all(b"hello").await
+        };
+        let mut buf = Vec::new();
+        let (read, write) = tokio::join!(rx.read_to_end(&mut buf), write);
+        write?;
+        assert_eq!(read?, 0);
+        assert_elapsed(start, Duration::from_secs(6) + TIMEOUT);
+        assert!(idle.load(Ordering::Relaxed));
+
+        let mut received = [0; 5];
+        client.read_exact(&mut received).await?;
+        assert_eq!(&received, b"hello");
+        Ok(())
+    }
+}
@@ -12,7 +12,10 @@
 mod connection_acceptor;
 mod errors;
 mod http_service;
+mod idle_timeout;
 mod netspeedtest;
+mod permit_stream;
+mod proxy_protocol;
 mod repo_handlers;
 mod request_handler;
 mod wireproto_sink;
@@ -20,9 +23,11 @@ mod wireproto_sink;
 use std::path::PathBuf;
 use std::sync::atomic::AtomicBool;
 use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::Context as _;
 use anyhow::Result;
+use arc_swap::ArcSwap;
 use cached_config::ConfigStore;
 use cmdlib::monitoring::ReadyFlagService;
 use fbinit::FacebookInit;
@@ -39,6 +44,9 @@ use slog::Logger;
 
 use crate::connection_acceptor::connection_acceptor;
 pub use crate::connection_acceptor::wait_for_connections_closed;
+pub use crate::connection_acceptor::KeepaliveOptions;
+pub use crate::connection_acceptor::ListeningSocket;
+pub use crate::connection_acceptor::TcpOptions;
 
 const CONFIGERATOR_RATE_LIMITING_CONFIG: &str = "scm/mononoke/ratelimiting/ratelimits";
 
@@ -47,8 +55,8 @@ pub async fn create_repo_listeners<'a>(
     common_config: CommonConfig,
     mononoke: Arc<Mononoke>,
     root_log: Logger,
-    sockname: String,
-    tls_acceptor: SslAcceptor,
+    sockets: Vec<ListeningSocket>,
+    tls_acceptor: Arc<ArcSwap<SslAcceptor>>,
     service: ReadyFlagService,
     terminate_process: oneshot::Receiver<()>,
     config_store: &'a ConfigStore,
@@ -59,6 +67,10 @@ pub async fn create_repo_listeners<'a>(
     bound_addr_file: Option<PathBuf>,
     acl_provider: &dyn AclProvider,
     readonly: bool,
+    expect_proxy_protocol: bool,
+    max_connections: Option<usize>,
+    connection_idle_timeout: Option<Duration>,
+    keepalive: KeepaliveOptions,
 ) -> Result<()> {
     let rate_limiter = {
         let handle = config_store
@@ -95,7 +107,7 @@ pub async fn create_repo_listeners<'a>(
     connection_acceptor(
         fb,
         common_config,
-        sockname,
+        sockets,
         service,
         root_log,
         mononoke,
@@ -115,6 +127,10 @@ pub async fn create_repo_listeners<'a>(
         bound_addr_file,
         acl_provider,
         readonly,
+        expect_proxy_protocol,
+        max_connections,
+        connection_idle_timeout,
+        keepalive,
     )
     .await
 }
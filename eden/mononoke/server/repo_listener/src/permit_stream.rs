@@ -0,0 +1,139 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::io::Error;
+use std::pin::Pin;
+
+use futures::task::Context;
+use futures::task::Poll;
+use pin_project::pin_project;
+use tokio::io::AsyncRead;
+use tokio::io::AsyncWrite;
+use tokio::io::ReadBuf;
+use tokio::sync::OwnedSemaphorePermit;
+
+/// A stream that holds on to its connection limit permit until it is dropped, so that the
+/// connection keeps counting towards the limit after being upgraded and handed off to another
+/// task, eg: for the wireproto over a websocket.
+#[pin_project]
+pub struct PermitStream<T> {
+    #[pin]
+    inner: T,
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl<T> PermitStream<T> {
+    pub fn new(inner: T, permit: Option<OwnedSemaphorePermit>) -> Self {
+        Self {
+            inner,
+            _permit: permit,
+        }
+    }
+}
+
+impl<T> AsyncRead for PermitStream<T>
+where
+    T: AsyncRead,
+{
+    #[inline]
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<(), Error>> {
+        let this = self.project();
+        this.inner.poll_read(cx, buf)
+    }
+}
+
+impl<T> AsyncWrite for PermitStream<T>
+where
+    T: AsyncWrite,
+{
+    #[inline]
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &[u8],
+    ) -> Poll<Result<usize, Error>> {
+        let this = self.project();
+        this.inner.poll_write(cx, buf)
+    }
+
+    #[inline]
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Error>> {
+        let this = self.project();
+        this.inner.poll_flush(cx)
+    }
+
+    #[inline]
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Error>> {
+        let this = self.project();
+        this.inner.poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use anyhow::Result;
+    use hyper::server::conn::Http;
+    use hyper::service::service_fn;
+    use hyper::Body;
+    use hyper::Request;
+    use hyper::Response;
+    use tokio::io::AsyncWriteExt;
+    use tokio::sync::Semaphore;
+    use tokio::time::timeout;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_permit_held_after_upgrade() -> Result<()> {
+        let limit = Arc::new(Semaphore::new(1));
+        let permit = limit.clone().try_acquire_owned()?;
+        let (mut client, server) = tokio::io::duplex(1024);
+
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        let svc = service_fn(move |req: Request<Body>| {
+            let sender = sender.clone();
+            async move {
+                // Like for the wireproto over a websocket, hand off the upgraded connection to
+                // another task.
+                tokio::spawn(async move { sender.send(hyper::upgrade::on(req).await) });
+                Response::builder()
+                    .status(http::StatusCode::SWITCHING_PROTOCOLS)
+                    .header(http::header::CONNECTION, "upgrade")
+                    .header(http::header::UPGRADE, "websocket")
+                    .body(Body::empty())
+            }
+        });
+
+        client
+            .write_all(
+                b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: upgrade\r\nUpgrade: websocket\r\n\r\n",
+            )
+            .await?;
+        Http::new()
+            .http1_only(true)
+            .serve_connection(PermitStream::new(server, Some(permit)), svc)
+            .with_upgrades()
+            .await?;
+
+        // Serving HTTP is done, but the upgraded connection is still open.
+        let upgraded = timeout(Duration::from_secs(10), receiver.recv())
+            .await?
+            .unwrap()?;
+        assert_eq!(limit.available_permits(), 0);
+
+        drop(upgraded);
+        assert_eq!(limit.available_permits(), 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,285 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Parsing of the PROXY protocol header that L4 load balancers send ahead of the proxied
+//! connection, as described in https://www.haproxy.org/download/2.6/doc/proxy-protocol.txt.
+
+use std::net::IpAddr;
+use std::net::Ipv4Addr;
+use std::net::Ipv6Addr;
+use std::net::SocketAddr;
+
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use tokio::io::AsyncRead;
+use tokio::io::AsyncReadExt;
+
+const V1_PREFIX: &[u8] = b"PROXY ";
+const V1_MAX_LEN: usize = 107;
+const V2_SIGNATURE: &[u8] = b"\r\n\r\n\0\r\nQUIT\n";
+
+/// Read a PROXY protocol v1 or v2 header off the stream, consuming exactly the header. Returns
+/// the address of the original client, or None if the proxy did not provide one (e.g. for its
+/// own health checks).
+pub async fn read_proxy_header<S: AsyncRead + Unpin>(stream: &mut S) -> Result<Option<SocketAddr>> {
+    // Both versions can be told apart from the length of the v1 prefix.
+    let mut prefix = [0; V1_PREFIX.len()];
+    stream
+        .read_exact(&mut prefix)
+        .await
+        .context("Failed to read PROXY protocol header")?;
+
+    if prefix == V1_PREFIX {
+        read_v1(stream).await
+    } else if prefix == V2_SIGNATURE[..V1_PREFIX.len()] {
+        read_v2(stream).await
+    } else {
+        bail!("Connection does not start with a PROXY protocol header");
+    }
+}
+
+async fn read_v1<S: AsyncRead + Unpin>(stream: &mut S) -> Result<Option<SocketAddr>> {
+    // The header is a single line, so read it byte by byte to avoid consuming what follows.
+    let mut line = Vec::with_capacity(V1_MAX_LEN - V1_PREFIX.len());
+    while !line.ends_with(b"\r\n") {
+        if line.len() + V1_PREFIX.len() >= V1_MAX_LEN {
+            bail!("PROXY protocol v1 header is too long");
+        }
+        line.push(
+            stream
+                .read_u8()
+                .await
+                .context("Failed to read PROXY protocol v1 header")?,
+        );
+    }
+    line.truncate(line.len() - 2);
+
+    let line = std::str::from_utf8(&line).context("PROXY protocol v1 header is not ASCII")?;
+    let fields: Vec<&str> = line.split(' ').collect();
+    match fields.as_slice() {
+        ["UNKNOWN", ..] => Ok(None),
+        [proto @ ("TCP4" | "TCP6"), src_ip, _dst_ip, src_port, _dst_port] => {
+            let ip: IpAddr = src_ip
+                .parse()
+                .with_context(|| format!("Invalid source address '{}'", src_ip))?;
+            if ip.is_ipv4() != (*proto == "TCP4") {
+                bail!("Source address '{}' does not match {}", src_ip, proto);
+            }
+            let port: u16 = src_port
+                .parse()
+                .with_context(|| format!("Invalid source port '{}'", src_port))?;
+            Ok(Some(SocketAddr::new(ip, port)))
+        }
+        _ => bail!("Malformed PROXY protocol v1 header '{}'", line),
+    }
+}
+
+async fn read_v2<S: AsyncRead + Unpin>(stream: &mut S) -> Result<Option<SocketAddr>> {
+    let mut header = [0; 16];
+    header[..V1_PREFIX.len()].copy_from_slice(&V2_SIGNATURE[..V1_PREFIX.len()]);
+    stream
+        .read_exact(&mut header[V1_PREFIX.len()..])
+        .await
+        .context("Failed to read PROXY protocol v2 header")?;
+    if &header[..V2_SIGNATURE.len()] != V2_SIGNATURE {
+        bail!("Invalid PROXY protocol v2 signature");
+    }
+
+    let version = header[12] >> 4;
+    let command = header[12] & 0x0f;
+    let family = header[13] >> 4;
+    let len = u16::from_be_bytes([header[14], header[15]]) as usize;
+    if version != 2 {
+        bail!("Unsupported PROXY protocol version {}", version);
+    }
+
+    let mut addresses = vec![0; len];
+    stream
+        .read_exact(&mut addresses)
+        .await
+        .context("Failed to read PROXY protocol v2 addresses")?;
+
+    match command {
+        // LOCAL connections are established by the proxy itself.
+        0x0 => return Ok(None),
+        0x1 => {}
+        _ => bail!("Unsupported PROXY protocol v2 command {}", command),
+    }
+
+    // Addresses are followed by optional TLVs, which we don't need.
+    match family {
+        // AF_UNSPEC
+        0x0 => Ok(None),
+        // AF_INET: source address, destination address, source port, destination port.
+        0x1 if len >= 12 => {
+            let ip = Ipv4Addr::from([addresses[0], addresses[1], addresses[2], addresses[3]]);
+            let port = u16::from_be_bytes([addresses[8], addresses[9]]);
+            Ok(Some(SocketAddr::new(ip.into(), port)))
+        }
+        // AF_INET6, with the same layout.
+        0x2 if len >= 36 => {
+            let mut ip = [0; 16];
+            ip.copy_from_slice(&addresses[..16]);
+            let port = u16::from_be_bytes([addresses[32], addresses[33]]);
+            Ok(Some(SocketAddr::new(Ipv6Addr::from(ip).into(), port)))
+        }
+        // AF_UNIX has no address we could use.
+        0x3 => Ok(None),
+        _ => bail!(
+            "Malformed PROXY protocol v2 header with family {} and length {}",
+            family,
+            len
+        ),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Parse the PROXY protocol header at the start of `bytes`, and return what follows it.
+    async fn parse(mut bytes: &[u8]) -> Result<(Option<SocketAddr>, &[u8])> {
+        let addr = read_proxy_header(&mut bytes).await?;
+        Ok((addr, bytes))
+    }
+
+    fn v2_header(command: u8, family: u8, addresses: &[u8]) -> Vec<u8> {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.push(0x20 | command);
+        // SOCK_STREAM
+        header.push(family << 4 | 0x1);
+        header.extend((addresses.len() as u16).to_be_bytes());
+        header.extend(addresses);
+        header
+    }
+
+    #[tokio::test]
+    async fn test_v1() -> Result<()> {
+        assert_eq!(
+            parse(b"PROXY TCP4 192.168.0.1 192.168.0.11 56324 443\r\nGET /").await?,
+            (Some("192.168.0.1:56324".parse()?), &b"GET /"[..])
+        );
+        assert_eq!(
+            parse(b"PROXY TCP6 ::1 ::2 56324 443\r\n").await?,
+            (Some("[::1]:56324".parse()?), &b""[..])
+        );
+        assert_eq!(
+            parse(b"PROXY UNKNOWN ffff::1 ffff::2 56324 443\r\nrest").await?,
+            (None, &b"rest"[..])
+        );
+        assert_eq!(parse(b"PROXY UNKNOWN\r\n").await?, (None, &b""[..]));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_v1_max_len() -> Result<()> {
+        // The longest allowed header is 107 bytes, CRLF included.
+        let header = |len: usize| {
+            let mut header = b"PROXY UNKNOWN ".to_vec();
+            header.resize(len - 2, b'x');
+            header.extend(b"\r\n");
+            header
+        };
+        assert_eq!(parse(&header(107)).await?, (None, &b""[..]));
+        assert!(parse(&header(108)).await.is_err());
+        // Without CRLF, reading stops at the limit rather than at the end of the stream.
+        assert!(parse(&[b"PROXY UNKNOWN ".as_ref(), &[b'x'; 1000]].concat())
+            .await
+            .is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_v1_malformed() -> Result<()> {
+        // The address family doesn't match the protocol.
+        assert!(parse(b"PROXY TCP4 ::1 ::2 56324 443\r\n").await.is_err());
+        assert!(parse(b"PROXY TCP6 192.168.0.1 192.168.0.11 56324 443\r\n")
+            .await
+            .is_err());
+        // Invalid fields.
+        assert!(parse(b"PROXY TCP4 192.168.0.1 192.168.0.11 56324\r\n")
+            .await
+            .is_err());
+        assert!(parse(b"PROXY TCP4 192.168.0.1 192.168.0.11 65536 443\r\n")
+            .await
+            .is_err());
+        assert!(parse(b"PROXY UDP4 192.168.0.1 192.168.0.11 56324 443\r\n")
+            .await
+            .is_err());
+        // Truncated header.
+        assert!(parse(b"PROXY TCP4 192.168.0.1").await.is_err());
+        // Not a PROXY protocol header at all.
+        assert!(parse(b"GET / HTTP/1.1\r\n").await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_v2() -> Result<()> {
+        let tcp4 = [192, 168, 0, 1, 192, 168, 0, 11, 0xdc, 0x04, 0x01, 0xbb];
+        let mut tcp6 = [0; 36];
+        tcp6[15] = 1;
+        tcp6[31] = 2;
+        tcp6[32..].copy_from_slice(&[0xdc, 0x04, 0x01, 0xbb]);
+
+        let mut bytes = v2_header(0x1, 0x1, &tcp4);
+        bytes.extend(b"GET /");
+        assert_eq!(
+            parse(&bytes).await?,
+            (Some("192.168.0.1:56324".parse()?), &b"GET /"[..])
+        );
+        assert_eq!(
+            parse(&v2_header(0x1, 0x2, &tcp6)).await?,
+            (Some("[::1]:56324".parse()?), &b""[..])
+        );
+
+        // TLVs after the addresses are skipped.
+        let mut bytes = v2_header(0x1, 0x1, &[&tcp4[..], &[0x04, 0x00, 0x01, 0x00]].concat());
+        bytes.extend(b"rest");
+        assert_eq!(
+            parse(&bytes).await?,
+            (Some("192.168.0.1:56324".parse()?), &b"rest"[..])
+        );
+
+        // LOCAL connections from the proxy itself ignore the addresses, but still consume them.
+        let mut bytes = v2_header(0x0, 0x1, &tcp4);
+        bytes.extend(b"rest");
+        assert_eq!(parse(&bytes).await?, (None, &b"rest"[..]));
+
+        // Unspecified and Unix domain socket families don't have a usable address.
+        assert_eq!(parse(&v2_header(0x1, 0x0, &[])).await?, (None, &b""[..]));
+        assert_eq!(
+            parse(&v2_header(0x1, 0x3, &[0; 216])).await?,
+            (None, &b""[..])
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_v2_malformed() -> Result<()> {
+        // The address block is too short for the family.
+        assert!(parse(&v2_header(0x1, 0x1, &[192, 168, 0, 1]))
+            .await
+            .is_err());
+        assert!(parse(&v2_header(0x1, 0x2, &[0; 12])).await.is_err());
+        // The stream ends before the announced address block.
+        let mut bytes = v2_header(0x1, 0x1, &[0; 12]);
+        bytes.truncate(bytes.len() - 1);
+        assert!(parse(&bytes).await.is_err());
+        // Unknown command, version and family.
+        assert!(parse(&v2_header(0x2, 0x1, &[0; 12])).await.is_err());
+        let mut bytes = v2_header(0x1, 0x1, &[0; 12]);
+        bytes[12] = 0x11;
+        assert!(parse(&bytes).await.is_err());
+        assert!(parse(&v2_header(0x1, 0x4, &[0; 12])).await.is_err());
+        // Only the start of the signature matches.
+        let mut bytes = v2_header(0x1, 0x1, &[0; 12]);
+        bytes[10] = b'X';
+        assert!(parse(&bytes).await.is_err());
+        Ok(())
+    }
+}
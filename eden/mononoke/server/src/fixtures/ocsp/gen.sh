@@ -0,0 +1,36 @@
+#!/bin/bash
+# Copyright (c) Meta Platforms, Inc. and affiliates.
+#
+# This software may be used and distributed according to the terms of the
+# GNU General Public License version 2.
+
+# Generate the certificates and OCSP responses for the tests of the OCSP stapler. The CA signs
+# the OCSP responses itself. Run from this directory.
+
+set -e
+
+workdir="$(mktemp -d)"
+trap 'rm -rf "$workdir"' EXIT
+
+openssl req -x509 -newkey rsa:2048 -nodes -keyout "$workdir/ca.key" -out ca.crt \
+  -subj /CN=ca -days 36500 2>/dev/null
+
+for name in server other; do
+  openssl req -newkey rsa:2048 -nodes -keyout "$workdir/$name.key" -out "$workdir/$name.csr" \
+    -subj "/CN=$name" 2>/dev/null
+  openssl x509 -req -in "$workdir/$name.csr" -CA ca.crt -CAkey "$workdir/ca.key" \
+    -CAcreateserial -CAserial "$workdir/ca.srl" -out "$name.crt" -days 36500 2>/dev/null
+  serial="$(openssl x509 -in "$name.crt" -noout -serial | cut -d= -f2)"
+  printf 'V\t491231235959Z\t\t%s\tunknown\t/CN=%s\n' "$serial" "$name" >> "$workdir/index.txt"
+done
+
+respond() {
+  openssl ocsp -index "$workdir/index.txt" -rsigner ca.crt -rkey "$workdir/ca.key" -CA ca.crt \
+    -issuer ca.crt -cert "$1" -respout "$2" "${@:3}" > /dev/null
+}
+
+# Without a next update, the response never goes stale.
+respond server.crt server.der
+respond other.crt other.der
+# Stale a minute after being generated, or a few more with the allowed clock skew.
+respond server.crt stale.der -nmin 1
@@ -7,19 +7,38 @@
 
 #![feature(never_type)]
 
+mod ocsp;
+
+use std::collections::BTreeSet;
+use std::ffi::OsStr;
+use std::ffi::OsString;
+use std::io;
+use std::io::Write;
+use std::net::IpAddr;
+use std::net::Ipv4Addr;
+use std::net::Ipv6Addr;
+use std::net::SocketAddr;
+use std::os::unix::io::RawFd;
 use std::path::PathBuf;
+use std::pin::Pin;
 use std::sync::atomic::AtomicBool;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
 
+use anyhow::anyhow;
 use anyhow::bail;
 use anyhow::Context;
 use anyhow::Result;
+use arc_swap::ArcSwap;
 use async_trait::async_trait;
 use cache_warmup::cache_warmup;
+use clap::ArgEnum;
 use clap::Parser;
 use cloned::cloned;
 use cmdlib_logging::ScribeLoggingArgs;
+use environment::Caching;
 use environment::WarmBookmarksCacheDerivedData;
 use executor_lib::args::ShardedExecutorArgs;
 use executor_lib::RepoShardedProcess;
@@ -30,6 +49,8 @@ use futures::stream;
 use futures::stream::StreamExt;
 use futures::stream::TryStreamExt;
 use futures_watchdog::WatchdogExt;
+use metaconfig_parser::RepoConfigs;
+use metaconfig_types::BlobConfig;
 use mononoke_api::CoreContext;
 use mononoke_api::Mononoke;
 use mononoke_api::Repo;
@@ -43,39 +64,154 @@ use mononoke_app::fb303::ReadyFlagService;
 use mononoke_app::MononokeApp;
 use mononoke_app::MononokeAppBuilder;
 use mononoke_repos::MononokeRepos;
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
 use openssl::ssl::AlpnError;
+use openssl::ssl::Ssl;
+use openssl::ssl::SslAcceptor;
+use openssl::ssl::SslVerifyMode;
+use openssl::ssl::SslVersion;
+use openssl::x509::X509;
+use repo_listener::KeepaliveOptions;
+use repo_listener::ListeningSocket;
+use repo_listener::TcpOptions;
 use slog::error;
 use slog::info;
 use slog::o;
+use slog::warn;
 use slog::Logger;
+use tempfile::NamedTempFile;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpListener;
+use tokio::net::TcpStream;
+use tokio::signal::unix::signal;
+use tokio::signal::unix::Signal;
+use tokio::signal::unix::SignalKind;
+use tokio_openssl::SslStream;
+
+use crate::ocsp::OcspStapler;
 
 const SM_CLEANUP_TIMEOUT_SECS: u64 = 120;
 
+/// How many seconds to wait for the thrift server to listen before serving it over TLS.
+const THRIFT_LISTEN_ATTEMPTS: usize = 60;
+
+/// The first file descriptor passed for socket activation.
+const SD_LISTEN_FDS_START: RawFd = 3;
+
+const TLS_CERT_ENV: &str = "MONONOKE_TLS_CERT";
+const TLS_PRIVATE_KEY_ENV: &str = "MONONOKE_TLS_PRIVATE_KEY";
+const TLS_CA_ENV: &str = "MONONOKE_TLS_CA";
+
 /// Mononoke Server
 #[derive(Parser)]
 struct MononokeServerArgs {
     #[clap(flatten)]
     shutdown_timeout_args: ShutdownTimeoutArgs,
+    /// Check that the repo configs are valid by building all the repos they enable, then exit
+    /// without serving anything
+    #[clap(long)]
+    validate_configs: bool,
+    /// Print the repo configs as JSON, after resolving the storage they refer to and filling in
+    /// the defaults, then exit without serving anything
+    #[clap(long)]
+    dump_config: bool,
+    /// Stop accepting connections as soon as a shutdown signal is received, rather than after
+    /// the shutdown grace period
+    #[clap(long)]
+    stop_accepting_on_signal: bool,
+    /// Once no longer accepting connections, drop the ones still open after this many seconds
+    /// and exit cleanly, rather than waiting up to the shutdown timeout and failing
+    #[clap(long)]
+    drain_timeout: Option<u64>,
     #[clap(flatten)]
     scribe_logging_args: ScribeLoggingArgs,
-    /// TCP address to listen to in format `host:port
+    /// TCP address to listen to in format `host:port`. Repeat to listen to several addresses.
+    /// Ignored if listening sockets are passed by systemd socket activation
+    #[clap(long)]
+    listening_host_port: Vec<String>,
+    /// Serve a repo on a dedicated TCP address, in addition to the shared ones, in format
+    /// `repo=host:port`. Connections to it can only use that repo. Repeat for several repos
+    #[clap(long, value_parser = parse_repo_listening_host_port)]
+    repo_listening_host_port: Vec<(String, String)>,
+    /// Path of a Unix domain socket to listen to, instead of or in addition to TCP
     #[clap(long)]
-    listening_host_port: String,
-    /// Path for file in which to write the bound tcp address in rust std::net::SocketAddr format
+    listening_uds: Option<PathBuf>,
+    /// Require TLS on the Unix domain socket
+    #[clap(long, requires = "listening-uds")]
+    listening_uds_tls: bool,
+    /// Only accept IPv6 connections on IPv6 TCP addresses, regardless of the OS default
+    #[clap(long, conflicts_with = "dual-stack")]
+    ipv6_only: bool,
+    /// Also accept IPv4-mapped connections on IPv6 TCP addresses, regardless of the OS default
+    #[clap(long)]
+    dual_stack: bool,
+    /// Set SO_REUSEPORT on the TCP sockets, so that a new instance can listen on the same
+    /// addresses before this one exits. To restart without downtime, start the new instance and
+    /// then send SIGTERM to the old one: it keeps accepting connections for the shutdown grace
+    /// period, then stops listening and waits for its open connections to close
+    #[clap(long)]
+    reuse_port: bool,
+    /// Retry binding the TCP addresses this many times before giving up, e.g. to wait for the
+    /// previous instance to release them during a restart
+    #[clap(long, default_value_t = 0)]
+    bind_retries: u32,
+    /// Seconds to wait before the first bind retry. The wait doubles with every retry
+    #[clap(long, default_value_t = 1)]
+    bind_retry_interval: u64,
+    /// Expect a PROXY protocol v1 or v2 header on each TCP connection, as sent by L4 load
+    /// balancers, and use the client address it carries. Connections without one are rejected
+    #[clap(long)]
+    expect_proxy_protocol: bool,
+    /// Stop accepting connections while this many are open. Unlimited if unset
+    #[clap(long)]
+    max_connections: Option<usize>,
+    /// Close connections after this many seconds without any bytes sent or received. Never
+    /// closed if unset
+    #[clap(long)]
+    connection_idle_timeout: Option<u64>,
+    /// Enable TCP keepalive, with probes starting after this many seconds without activity
+    #[clap(long)]
+    tcp_keepalive_idle: Option<u64>,
+    /// Enable TCP keepalive, with this many seconds between probes
+    #[clap(long)]
+    tcp_keepalive_interval: Option<u64>,
+    /// Enable TCP keepalive, closing connections after this many unanswered probes
+    #[clap(long)]
+    tcp_keepalive_probes: Option<u32>,
+    /// Path for file in which to write the first bound tcp address in rust std::net::SocketAddr format
     #[clap(long)]
     bound_address_file: Option<PathBuf>,
     /// If provided the thrift server will start on this port
     #[clap(long, short = 'p')]
     thrift_port: Option<String>,
-    /// Path to a file with server certificate
+    /// If provided the thrift server will also be served over TLS on this port, with the same TLS
+    /// config as the main listener. The thrift port keeps serving plaintext, so the server refuses
+    /// to start unless the thrift server only listens on the loopback interface
     #[clap(long)]
-    cert: String,
-    /// Path to a file with server private key
+    thrift_tls_port: Option<u16>,
+    /// If provided, serve a readiness probe over HTTP on this port. It responds with 200 once the
+    /// repo listeners are accepting connections, and with 503 before that or while shutting down
     #[clap(long)]
-    private_key: String,
-    /// Path to a file with CA certificate
+    readiness_port: Option<u16>,
+    /// Path to a file with server certificate. Alternatively, set $MONONOKE_TLS_CERT to its
+    /// PEM content, which is then only read at startup and isn't reloaded on SIGHUP
     #[clap(long)]
-    ca_pem: String,
+    cert: Option<String>,
+    /// Path to a file with server private key. Alternatively, set $MONONOKE_TLS_PRIVATE_KEY to
+    /// its PEM content, which is then only read at startup and isn't reloaded on SIGHUP
+    #[clap(long)]
+    private_key: Option<String>,
+    /// Path to a file with CA certificate. Alternatively, set $MONONOKE_TLS_CA to its PEM
+    /// content
+    #[clap(long)]
+    ca_pem: Option<String>,
+    /// Path to a PEM bundle with the server certificate, its chain and its private key, instead
+    /// of --cert and --private-key. The chain is used as the CA certificate unless --ca-pem or
+    /// $MONONOKE_TLS_CA is set. The bundle is only read at startup and isn't reloaded on SIGHUP
+    #[clap(long, conflicts_with_all = &["cert", "private-key"])]
+    tls_bundle: Option<String>,
     /// Path to a file with SCS client certificate
     #[clap(long)]
     scs_client_cert: Option<String>,
@@ -85,6 +221,35 @@ struct MononokeServerArgs {
     /// Path to a file with encryption keys for SSL tickets
     #[clap(long)]
     ssl_ticket_seeds: Option<String>,
+    /// Check the SSL ticket seeds file for changes every this many seconds, and re-key the TLS
+    /// acceptor when they change, so that ticket keys can be rotated without a restart
+    #[clap(long, requires = "ssl-ticket-seeds")]
+    ssl_ticket_seeds_reload_interval: Option<u64>,
+    /// Comma-separated list of protocols to accept over ALPN. If the client offers several of
+    /// them, the first one in the client's order of preference is selected. Clients that only offer
+    /// other protocols are rejected
+    #[clap(
+        long,
+        value_delimiter = ',',
+        default_value = "hgcli",
+        value_parser = parse_alpn_protocol
+    )]
+    alpn_protocols: Vec<&'static str>,
+    /// Minimum TLS version to accept: 1.0, 1.1, 1.2 or 1.3. Defaults to the OpenSSL default
+    #[clap(long, value_parser = parse_tls_version)]
+    tls_min_version: Option<SslVersion>,
+    /// Colon-separated list of TLS ciphers to accept, in OpenSSL format. TLS 1.3 cipher suites
+    /// (named `TLS_*`) and ciphers for older versions can be mixed
+    #[clap(long)]
+    tls_ciphers: Option<String>,
+    /// Whether clients must present a certificate. With `request`, clients without one are
+    /// served without identities, as a step towards requiring them
+    #[clap(long, arg_enum, default_value_t = ClientCertMode::Require)]
+    client_cert_mode: ClientCertMode,
+    /// Path to a DER-encoded OCSP response for the server certificate, to staple to TLS
+    /// handshakes. It is reloaded every minute, and not stapled while missing or stale
+    #[clap(long)]
+    ocsp_response: Option<PathBuf>,
     /// Top level Mononoke tier where CSLB publishes routing table
     #[clap(long)]
     cslb_config: Option<String>,
@@ -216,8 +381,777 @@ impl RepoShardedProcessExecutor for MononokeApiProcessExecutor {
     }
 }
 
+/// Read and clear the environment variables of sd_listen_fds(3), so that child processes don't
+/// think sockets were passed to them. This has to happen before any other thread is started, as
+/// modifying the environment isn't thread safe.
+fn take_systemd_listen_env() -> (Option<OsString>, Option<OsString>) {
+    let env = (
+        std::env::var_os("LISTEN_PID"),
+        std::env::var_os("LISTEN_FDS"),
+    );
+    for var in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+        std::env::remove_var(var);
+    }
+    env
+}
+
+/// The listening sockets passed to the process `pid` for systemd socket activation, as described
+/// in sd_listen_fds(3), given the values of $LISTEN_PID and $LISTEN_FDS.
+fn systemd_listen_fds(
+    listen_pid: Option<&OsStr>,
+    listen_fds: Option<&OsStr>,
+    pid: u32,
+) -> Result<Vec<RawFd>> {
+    let listen_pid = match listen_pid {
+        Some(listen_pid) => listen_pid,
+        None => return Ok(vec![]),
+    };
+    let listen_pid: u32 = listen_pid
+        .to_str()
+        .context("Invalid $LISTEN_PID")?
+        .parse()
+        .context("Invalid $LISTEN_PID")?;
+    if listen_pid != pid {
+        return Ok(vec![]);
+    }
+    let fds: RawFd = listen_fds
+        .context("$LISTEN_PID is set without $LISTEN_FDS")?
+        .to_str()
+        .context("Invalid $LISTEN_FDS")?
+        .parse()
+        .context("Invalid $LISTEN_FDS")?;
+    let end = SD_LISTEN_FDS_START
+        .checked_add(fds)
+        .filter(|_| fds >= 0)
+        .with_context(|| format!("Invalid $LISTEN_FDS {}", fds))?;
+    Ok((SD_LISTEN_FDS_START..end).collect())
+}
+
+fn parse_repo_listening_host_port(value: &str) -> Result<(String, String)> {
+    match value.split_once('=') {
+        Some((repo, addr)) if !repo.is_empty() && !addr.is_empty() => {
+            Ok((repo.to_string(), addr.to_string()))
+        }
+        _ => bail!("Expected repo=host:port, got '{}'", value),
+    }
+}
+
+fn parse_alpn_protocol(name: &str) -> Result<&'static str> {
+    alpn::KNOWN_ALPNS
+        .iter()
+        .find(|known| **known == name)
+        .copied()
+        .ok_or_else(|| {
+            anyhow!(
+                "Unknown ALPN protocol '{}', expected one of {:?}",
+                name,
+                alpn::KNOWN_ALPNS
+            )
+        })
+}
+
+fn parse_tls_version(version: &str) -> Result<SslVersion> {
+    match version {
+        "1.0" => Ok(SslVersion::TLS1),
+        "1.1" => Ok(SslVersion::TLS1_1),
+        "1.2" => Ok(SslVersion::TLS1_2),
+        "1.3" => Ok(SslVersion::TLS1_3),
+        _ => bail!(
+            "Unsupported TLS version '{}', expected one of 1.0, 1.1, 1.2 or 1.3",
+            version
+        ),
+    }
+}
+
+/// The path to a file with TLS material: either `path`, or a private temporary file with the PEM
+/// content of the `env` variable, which is kept until `pem_files` is dropped.
+fn tls_material_path(
+    path: Option<String>,
+    arg: &str,
+    env: &str,
+    pem_files: &mut Vec<NamedTempFile>,
+) -> Result<String> {
+    let pem = std::env::var_os(env)
+        .map(|pem| {
+            pem.into_string()
+                .map_err(|_| anyhow!("${} is not valid UTF-8", env))
+        })
+        .transpose()?;
+    match (path, pem) {
+        (Some(path), None) => Ok(path),
+        (None, Some(pem)) => write_pem_file(&pem, pem_files)
+            .with_context(|| format!("Failed to write ${} to a file", env)),
+        (Some(_), Some(_)) => bail!("Both --{} and ${} are set, only one is allowed", arg, env),
+        (None, None) => bail!("One of --{} or ${} is required", arg, env),
+    }
+}
+
+fn write_pem_file(pem: &str, pem_files: &mut Vec<NamedTempFile>) -> Result<String> {
+    let mut file = NamedTempFile::new().context("Failed to create a TLS material file")?;
+    file.write_all(pem.as_bytes())?;
+    let path = file
+        .path()
+        .to_str()
+        .context("Invalid TLS material file path")?
+        .to_string();
+    pem_files.push(file);
+    Ok(path)
+}
+
+/// The PEM blocks of a TLS bundle.
+struct TlsBundle {
+    cert: String,
+    chain: String,
+    private_key: String,
+}
+
+impl TlsBundle {
+    /// Split a bundle made of the server certificate followed by its chain, and its private key
+    /// anywhere in the file.
+    fn read(path: &str) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read TLS bundle {}", path))?;
+
+        let mut blocks = vec![];
+        let mut current: Option<(&str, String)> = None;
+        for line in content.lines().map(str::trim_end) {
+            if let Some(label) = pem_boundary(line, "BEGIN") {
+                if let Some((label, _)) = current {
+                    bail!("Unterminated {} in TLS bundle {}", label, path);
+                }
+                current = Some((label, String::new()));
+            }
+            let end = match &mut current {
+                Some((label, pem)) => {
+                    pem.push_str(line);
+                    pem.push('\n');
+                    pem_boundary(line, "END") == Some(*label)
+                }
+                None => false,
+            };
+            if end {
+                blocks.extend(current.take());
+            }
+        }
+        if let Some((label, _)) = current {
+            bail!("Unterminated {} in TLS bundle {}", label, path);
+        }
+
+        let mut certs = vec![];
+        let mut keys = vec![];
+        for (label, pem) in blocks {
+            if label == "CERTIFICATE" {
+                certs.push(pem);
+            } else if label.ends_with("PRIVATE KEY") {
+                keys.push(pem);
+            }
+        }
+        if certs.is_empty() {
+            bail!("TLS bundle {} has no certificate", path);
+        }
+        let private_key = match keys.as_slice() {
+            [key] => key.clone(),
+            [] => bail!("TLS bundle {} has no private key", path),
+            _ => bail!("TLS bundle {} has several private keys", path),
+        };
+
+        let cert = certs.remove(0);
+        let public_key = X509::from_pem(cert.as_bytes())
+            .and_then(|cert| cert.public_key())
+            .with_context(|| format!("Invalid certificate in TLS bundle {}", path))?;
+        let key = PKey::private_key_from_pem(private_key.as_bytes())
+            .with_context(|| format!("Invalid private key in TLS bundle {}", path))?;
+        if !public_key.public_eq(&key) {
+            bail!(
+                "The private key in TLS bundle {} does not match its certificate",
+                path
+            );
+        }
+
+        Ok(Self {
+            cert,
+            chain: certs.concat(),
+            private_key,
+        })
+    }
+}
+
+/// The label of a `-----BEGIN <label>-----` or `-----END <label>-----` line.
+fn pem_boundary<'a>(line: &'a str, boundary: &str) -> Option<&'a str> {
+    line.strip_prefix("-----")?
+        .strip_prefix(boundary)?
+        .strip_prefix(' ')?
+        .strip_suffix("-----")
+}
+
+/// Reload the repo configs every time SIGHUP is received, without dropping existing connections.
+async fn reload_configs_on_sighup(
+    app: Arc<MononokeApp>,
+    mononoke: Arc<Mononoke>,
+    mut hangup: Signal,
+) {
+    while hangup.recv().await.is_some() {
+        info!(app.logger(), "Received SIGHUP, reloading repo configs");
+        if let Err(e) = reload_configs(&app, &mononoke).await {
+            error!(app.logger(), "Failed to reload repo configs: {:?}", e);
+        }
+    }
+}
+
+/// Reload the repo configs. Added repos start being served if they would have been at startup,
+/// and repos that were removed or disabled stop being served. Repos that keep being served are
+/// rebuilt with their new configs when the configs are reloaded, so changed ones are warmed up
+/// again.
+async fn reload_configs(app: &MononokeApp, mononoke: &Mononoke) -> Result<()> {
+    let old_configs = app.reload_configs().await?;
+    let new_configs = app.repo_configs();
+    let repo_filter = app.environment().filter_repos.clone();
+    let is_served = |name: &str| mononoke.repos.get_by_name(name).is_some();
+
+    let mut added = vec![];
+    let mut changed = vec![];
+    let mut removed = vec![];
+    for (name, config) in &new_configs.repos {
+        match old_configs.repos.get(name) {
+            Some(old_config) if old_config == config => {}
+            Some(_) if is_served(name) => {
+                // Served repos, deep-sharded ones included, were rebuilt with their new configs
+                // by the reload, so only the disabled ones stop being served.
+                if config.enabled {
+                    warm_up_cache(app, mononoke, name).await?;
+                    changed.push(name.as_str());
+                } else {
+                    app.remove_repo(&mononoke.repos, name);
+                    removed.push(name.as_str());
+                }
+            }
+            _ => {
+                let is_matching_filter = repo_filter.as_ref().map_or(true, |re| re.is_match(name));
+                if config.enabled && !config.deep_sharded && is_matching_filter {
+                    app.add_repo(&mononoke.repos, name).await?;
+                    warm_up_cache(app, mononoke, name).await?;
+                    added.push(name.as_str());
+                }
+            }
+        }
+    }
+    for name in old_configs.repos.keys() {
+        if !new_configs.repos.contains_key(name) && is_served(name) {
+            app.remove_repo(&mononoke.repos, name);
+            removed.push(name.as_str());
+        }
+    }
+
+    info!(
+        app.logger(),
+        "Reloaded repo configs. Added: {:?}, removed: {:?}, changed: {:?}", added, removed, changed,
+    );
+    Ok(())
+}
+
+async fn warm_up_cache(app: &MononokeApp, mononoke: &Mononoke, repo_name: &str) -> Result<()> {
+    let repo = mononoke
+        .repos
+        .get_by_name(repo_name)
+        .with_context(|| format!("Repo {} does not exist in MononokeRepos", repo_name))?;
+    let ctx = CoreContext::new_with_logger(app.fb, app.repo_logger(repo_name));
+    cache_warmup(&ctx, repo.blob_repo(), repo.config().cache_warmup.clone())
+        .await
+        .with_context(|| format!("Error while warming up cache for repo {}", repo_name))
+}
+
+#[derive(ArgEnum, Clone, Copy, Debug)]
+#[clap(rename_all = "lower")]
+enum ClientCertMode {
+    /// Reject clients without a valid certificate
+    Require,
+    /// Verify the certificate of clients that present one
+    Request,
+    /// Don't ask clients for a certificate
+    None,
+}
+
+impl ClientCertMode {
+    fn verify_mode(self) -> SslVerifyMode {
+        match self {
+            Self::Require => SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT,
+            Self::Request => SslVerifyMode::PEER,
+            Self::None => SslVerifyMode::NONE,
+        }
+    }
+}
+
+/// What the TLS acceptor is built from, kept around to rebuild it when certificates rotate.
+struct TlsConfig {
+    ca_pem: String,
+    cert: String,
+    private_key: String,
+    ssl_ticket_seeds: Option<String>,
+    alpn_protocols: Vec<&'static str>,
+    min_version: Option<SslVersion>,
+    ciphers: Option<String>,
+    client_cert_mode: ClientCertMode,
+    ocsp: Option<Arc<OcspStapler>>,
+}
+
+impl TlsConfig {
+    fn build_acceptor(&self, logger: &Logger) -> Result<SslAcceptor> {
+        let mut builder = secure_utils::SslConfig::new(
+            self.ca_pem.clone(),
+            self.cert.clone(),
+            self.private_key.clone(),
+            self.ssl_ticket_seeds.clone(),
+        )
+        .tls_acceptor_builder(logger.clone())
+        .context("Failed to instantiate TLS Acceptor builder")?;
+
+        let alpn_protocols = self.alpn_protocols.clone();
+        // Clients that offer protocols but none we accept fail the handshake with a "no
+        // application protocol" alert, so that they are counted as ALPN mismatches.
+        builder.set_alpn_select_callback(move |_, protos| {
+            alpn::alpn_select_any(protos, &alpn_protocols)
+                .map_err(|_| AlpnError::ALERT_FATAL)?
+                .ok_or(AlpnError::ALERT_FATAL)
+        });
+
+        if let Some(min_version) = self.min_version {
+            builder
+                .set_min_proto_version(Some(min_version))
+                .context("Failed to set the minimum TLS version")?;
+        }
+
+        if let Some(ciphers) = &self.ciphers {
+            // OpenSSL configures TLS 1.3 cipher suites separately.
+            let (suites, ciphers): (Vec<&str>, Vec<&str>) =
+                ciphers.split(':').partition(|c| c.starts_with("TLS_"));
+            if !ciphers.is_empty() {
+                builder
+                    .set_cipher_list(&ciphers.join(":"))
+                    .with_context(|| format!("Unsupported TLS ciphers '{}'", ciphers.join(":")))?;
+            }
+            if !suites.is_empty() {
+                builder
+                    .set_ciphersuites(&suites.join(":"))
+                    .with_context(|| {
+                        format!("Unsupported TLS 1.3 cipher suites '{}'", suites.join(":"))
+                    })?;
+            }
+        }
+
+        builder.set_verify(self.client_cert_mode.verify_mode());
+
+        if let Some(ocsp) = &self.ocsp {
+            let ocsp = ocsp.clone();
+            builder
+                .set_status_callback(move |ssl| ocsp.staple(ssl))
+                .context("Failed to set up OCSP stapling")?;
+        }
+
+        Ok(builder.build())
+    }
+}
+
+fn cert_fingerprint(acceptor: &SslAcceptor) -> String {
+    match acceptor
+        .context()
+        .certificate()
+        .map(|cert| cert.digest(MessageDigest::sha256()))
+    {
+        Some(Ok(digest)) => digest.iter().map(|b| format!("{:02x}", b)).collect(),
+        Some(Err(_)) => "<invalid>".to_string(),
+        None => "<none>".to_string(),
+    }
+}
+
+/// Check that the repo configs, which were parsed when building the app, describe repos that can
+/// all be built.
+async fn validate_configs(app: &Arc<MononokeApp>) -> Result<()> {
+    let configs = app.repo_configs();
+    info!(
+        app.logger(),
+        "Loaded configs for {} repos, building them",
+        configs.repos.len()
+    );
+    Mononoke::new(Arc::clone(app))
+        .await
+        .context("Failed to build repos from their configs")?;
+    info!(app.logger(), "Repo configs are valid");
+    Ok(())
+}
+
+/// The configs as the server sees them, as JSON.
+fn dump_config(configs: &RepoConfigs) -> Result<String> {
+    serde_json::to_string_pretty(configs).context("Failed to serialize the repo configs")
+}
+
+/// Log the effective configuration, to be able to tell how a server was deployed from its logs.
+fn log_startup_summary(
+    logger: &Logger,
+    configs: &RepoConfigs,
+    sockets: &[ListeningSocket],
+    acceptor: &SslAcceptor,
+    caching: Caching,
+    readonly: bool,
+) {
+    let enabled_repos = configs
+        .repos
+        .values()
+        .filter(|config| config.enabled)
+        .collect::<Vec<_>>();
+    let mut storage_backends = BTreeSet::new();
+    for config in &enabled_repos {
+        add_storage_backends(&config.storage_config.blobstore, &mut storage_backends);
+    }
+    let listening = sockets
+        .iter()
+        .map(|socket| match socket {
+            ListeningSocket::Tcp {
+                addr,
+                repo: Some(repo),
+                ..
+            } => format!("{} (only {})", addr, repo),
+            ListeningSocket::Tcp { addr, .. } => addr.clone(),
+            ListeningSocket::Uds { path, .. } => path.display().to_string(),
+            ListeningSocket::Inherited(fd) => format!("fd {}", fd),
+        })
+        .collect::<Vec<_>>();
+    let (cert_subject, cert_expiry) = match acceptor.context().certificate() {
+        Some(cert) => (
+            cert.subject_name()
+                .entries()
+                .map(|entry| {
+                    let value = entry
+                        .data()
+                        .as_utf8()
+                        .map_or_else(|_| "<invalid>".to_string(), |value| value.to_string());
+                    format!(
+                        "{}={}",
+                        entry.object().nid().short_name().unwrap_or("?"),
+                        value
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join(", "),
+            cert.not_after().to_string(),
+        ),
+        None => ("<none>".to_string(), "<none>".to_string()),
+    };
+    let caching = match caching {
+        Caching::Enabled(shards) => format!("enabled with {} shards", shards),
+        Caching::CachelibOnlyBlobstore(shards) => {
+            format!("blobstore only with {} shards", shards)
+        }
+        Caching::Disabled => "disabled".to_string(),
+    };
+
+    info!(
+        logger,
+        "Effective configuration";
+        "repos" => enabled_repos.len(),
+        "storage_backends" => format!("{:?}", storage_backends),
+        "listening" => format!("{:?}", listening),
+        "tls_cert_subject" => cert_subject,
+        "tls_cert_expiry" => cert_expiry,
+        "caching" => caching,
+        "readonly" => readonly,
+    );
+}
+
+fn add_storage_backends(config: &BlobConfig, backends: &mut BTreeSet<&'static str>) {
+    match config {
+        BlobConfig::Disabled => {}
+        BlobConfig::Files { .. } => {
+            backends.insert("files");
+        }
+        BlobConfig::Sqlite { .. } => {
+            backends.insert("sqlite");
+        }
+        BlobConfig::Manifold { .. } | BlobConfig::ManifoldWithTtl { .. } => {
+            backends.insert("manifold");
+        }
+        BlobConfig::Mysql { .. } => {
+            backends.insert("mysql");
+        }
+        BlobConfig::S3 { .. } => {
+            backends.insert("s3");
+        }
+        BlobConfig::Multiplexed { blobstores, .. }
+        | BlobConfig::MultiplexedWal { blobstores, .. } => {
+            for (_, _, config) in blobstores {
+                add_storage_backends(config, backends);
+            }
+        }
+        BlobConfig::Logging { blobconfig, .. } | BlobConfig::Pack { blobconfig, .. } => {
+            add_storage_backends(blobconfig, backends);
+        }
+    }
+}
+
+/// Rebuild the TLS acceptor from the same files on SIGHUP, so that rotated certificates are
+/// picked up by new connections. Established connections keep using the old ones.
+///
+/// Certificates that were passed through the environment or in a bundle were copied to temporary
+/// files at startup, so they aren't `reloadable`: rebuilding from the copies would change nothing.
+async fn reload_tls_on_sighup(
+    logger: Logger,
+    tls_config: Arc<TlsConfig>,
+    acceptor: Arc<ArcSwap<SslAcceptor>>,
+    reloadable: bool,
+    mut hangup: Signal,
+) {
+    while hangup.recv().await.is_some() {
+        info!(logger, "Received SIGHUP, reloading TLS certificates");
+        if let Some(ocsp) = &tls_config.ocsp {
+            ocsp.reload();
+        }
+        if !reloadable {
+            warn!(
+                logger,
+                "Not reloading TLS certificates passed through the environment or --tls-bundle, \
+                 the server has to be restarted to rotate them"
+            );
+            continue;
+        }
+        match tls_config.build_acceptor(&logger) {
+            Ok(new_acceptor) => {
+                let new_fingerprint = cert_fingerprint(&new_acceptor);
+                let old_acceptor = acceptor.swap(Arc::new(new_acceptor));
+                info!(
+                    logger,
+                    "Swapped TLS certificate {} for {}",
+                    cert_fingerprint(&old_acceptor),
+                    new_fingerprint
+                );
+            }
+            Err(e) => error!(logger, "Failed to reload TLS certificates: {:?}", e),
+        }
+    }
+}
+
+/// Rebuild the TLS acceptor whenever the contents of the SSL ticket seeds file change, checking
+/// every `interval`. New connections then use the new ticket keys.
+async fn reload_ticket_seeds_periodically(
+    logger: Logger,
+    tls_config: Arc<TlsConfig>,
+    acceptor: Arc<ArcSwap<SslAcceptor>>,
+    path: String,
+    interval: Duration,
+) {
+    let mut seeds = std::fs::read(&path).ok();
+    loop {
+        tokio::time::sleep(interval).await;
+        let new_seeds = match std::fs::read(&path) {
+            Ok(new_seeds) => Some(new_seeds),
+            Err(e) => {
+                warn!(
+                    logger,
+                    "Failed to read SSL ticket seeds from {}: {}", path, e
+                );
+                continue;
+            }
+        };
+        if new_seeds == seeds {
+            continue;
+        }
+        match tls_config.build_acceptor(&logger) {
+            Ok(new_acceptor) => {
+                acceptor.store(Arc::new(new_acceptor));
+                seeds = new_seeds;
+                info!(logger, "Rotated SSL ticket keys from {}", path);
+            }
+            Err(e) => error!(logger, "Failed to rotate SSL ticket keys: {:?}", e),
+        }
+    }
+}
+
+/// Serve the plaintext thrift service on `thrift_port` over TLS on `port`, by forwarding each
+/// connection decrypted to the thrift service over the loopback interface.
+async fn serve_thrift_over_tls(
+    logger: Logger,
+    acceptor: Arc<ArcSwap<SslAcceptor>>,
+    port: u16,
+    thrift_port: u16,
+) -> Result<()> {
+    let listener = TcpListener::bind((Ipv6Addr::UNSPECIFIED, port))
+        .await
+        .with_context(|| format!("could not bind thrift TLS port {}", port))?;
+    info!(logger, "Serving thrift over TLS on port {}", port);
+    loop {
+        let (stream, addr) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                error!(logger, "Failed to accept thrift TLS connection: {}", e);
+                continue;
+            }
+        };
+        let acceptor = acceptor.load_full();
+        let logger = logger.clone();
+        tokio::spawn(async move {
+            if let Err(e) = forward_thrift_connection(&acceptor, stream, thrift_port).await {
+                warn!(
+                    logger,
+                    "Failed to forward thrift connection from {}: {:?}", addr, e
+                );
+            }
+        });
+    }
+}
+
+/// Fail unless the thrift server listens on `port` on the loopback interface only, waiting for it
+/// to start listening.
+async fn ensure_thrift_port_is_local(port: u16) -> Result<()> {
+    for _ in 0..THRIFT_LISTEN_ATTEMPTS {
+        let mut addrs = vec![];
+        for path in ["/proc/net/tcp", "/proc/net/tcp6"] {
+            match std::fs::read_to_string(path) {
+                Ok(table) => addrs.extend(
+                    tcp_listen_addrs(&table, port).with_context(|| format!("Invalid {}", path))?,
+                ),
+                // There is no IPv6 table without IPv6 support.
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path)),
+            }
+        }
+        if let Some(addr) = addrs.iter().find(|addr| !addr.is_loopback()) {
+            bail!(
+                "The thrift server listens on {} port {} in plaintext, refusing to serve it over TLS",
+                addr,
+                port
+            );
+        }
+        if !addrs.is_empty() {
+            return Ok(());
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+    bail!("The thrift server isn't listening on port {}", port)
+}
+
+/// The addresses listening for TCP connections on `port` in `table`, the content of
+/// `/proc/net/tcp` or `/proc/net/tcp6`.
+fn tcp_listen_addrs(table: &str, port: u16) -> Result<Vec<IpAddr>> {
+    let mut addrs = vec![];
+    // The first line is a header.
+    for line in table.lines().skip(1) {
+        let (local, state) = match line.split_whitespace().collect::<Vec<_>>().as_slice() {
+            [_, local, _, state, ..] => (*local, *state),
+            _ => bail!("Invalid socket '{}'", line),
+        };
+        // The state of listening sockets is TCP_LISTEN.
+        if state != "0A" {
+            continue;
+        }
+        let (addr, local_port) = local
+            .split_once(':')
+            .with_context(|| format!("Invalid local address '{}'", local))?;
+        let local_port = u16::from_str_radix(local_port, 16)
+            .with_context(|| format!("Invalid local address '{}'", local))?;
+        if local_port == port {
+            addrs.push(parse_proc_net_addr(addr)?);
+        }
+    }
+    Ok(addrs)
+}
+
+/// Addresses in `/proc/net` are printed as hexadecimal 32-bit words in host byte order.
+fn parse_proc_net_addr(hex: &str) -> Result<IpAddr> {
+    let invalid = || anyhow!("Invalid address '{}'", hex);
+    if !hex.is_ascii() || hex.len() % 8 != 0 {
+        return Err(invalid());
+    }
+    let bytes = (0..hex.len())
+        .step_by(8)
+        .map(|start| u32::from_str_radix(&hex[start..start + 8], 16).map(u32::to_ne_bytes))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| invalid())?
+        .concat();
+    if let Ok(bytes) = <[u8; 4]>::try_from(bytes.as_slice()) {
+        Ok(Ipv4Addr::from(bytes).into())
+    } else if let Ok(bytes) = <[u8; 16]>::try_from(bytes.as_slice()) {
+        Ok(Ipv6Addr::from(bytes).into())
+    } else {
+        Err(invalid())
+    }
+}
+
+async fn forward_thrift_connection(
+    acceptor: &SslAcceptor,
+    stream: TcpStream,
+    thrift_port: u16,
+) -> Result<()> {
+    let ssl = Ssl::new(acceptor.context()).context("Error creating Ssl")?;
+    let mut tls_stream = SslStream::new(ssl, stream).context("Error creating SslStream")?;
+    Pin::new(&mut tls_stream)
+        .accept()
+        .await
+        .context("Failed to perform tls handshake")?;
+    // The thrift service may be listening on IPv6 only, or on IPv4 only.
+    let thrift_addrs = [
+        SocketAddr::from((Ipv6Addr::LOCALHOST, thrift_port)),
+        SocketAddr::from((Ipv4Addr::LOCALHOST, thrift_port)),
+    ];
+    let mut thrift = TcpStream::connect(&thrift_addrs[..])
+        .await
+        .context("Failed to connect to the thrift service")?;
+    tokio::io::copy_bidirectional(&mut tls_stream, &mut thrift).await?;
+    Ok(())
+}
+
+/// Answer every connection on `port` with the readiness of the server, in a minimal HTTP
+/// response regardless of what was requested.
+async fn serve_readiness(
+    logger: Logger,
+    port: u16,
+    service: ReadyFlagService,
+    will_exit: Arc<AtomicBool>,
+) -> Result<()> {
+    let listener = TcpListener::bind((Ipv6Addr::UNSPECIFIED, port))
+        .await
+        .with_context(|| format!("could not bind readiness port {}", port))?;
+    info!(logger, "Serving readiness on port {}", port);
+    loop {
+        let (mut stream, addr) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                error!(logger, "Failed to accept readiness connection: {}", e);
+                continue;
+            }
+        };
+        let ready = service.is_ready() && !will_exit.load(Ordering::Relaxed);
+        let logger = logger.clone();
+        tokio::spawn(async move {
+            let (status, body) = if ready {
+                ("200 OK", "ready\n")
+            } else {
+                ("503 Service Unavailable", "not ready\n")
+            };
+            let response = format!(
+                "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            );
+            // Read the request so that closing the connection doesn't reset it.
+            let mut request = [0; 1024];
+            let res = async {
+                let _ = stream.read(&mut request).await?;
+                stream.write_all(response.as_bytes()).await?;
+                stream.shutdown().await
+            };
+            if let Err(e) = res.await {
+                warn!(
+                    logger,
+                    "Failed to answer readiness probe from {}: {}", addr, e
+                );
+            }
+        });
+    }
+}
+
 #[fbinit::main]
 fn main(fb: FacebookInit) -> Result<()> {
+    // Before the runtime starts its threads.
+    let (listen_pid, listen_fds) = take_systemd_listen_env();
     let app = Arc::new(
         MononokeAppBuilder::new(fb)
             .with_default_scuba_dataset("mononoke_test_perf")
@@ -233,18 +1167,65 @@ fn main(fb: FacebookInit) -> Result<()> {
     let root_log = app.logger();
     let runtime = app.runtime();
 
+    if args.validate_configs {
+        return runtime.block_on(validate_configs(&app));
+    }
+
+    if args.dump_config {
+        println!("{}", dump_config(&app.repo_configs())?);
+        return Ok(());
+    }
+
     let cslb_config = args.cslb_config.clone();
     info!(root_log, "Starting up");
 
+    // The files with the TLS material passed through the environment must outlive the server.
+    let mut pem_files = vec![];
+    let certs_reloadable = args.tls_bundle.is_none()
+        && [TLS_CERT_ENV, TLS_PRIVATE_KEY_ENV]
+            .iter()
+            .all(|env| std::env::var_os(env).is_none());
+    let (cert, private_key, ca_pem) = match args.tls_bundle {
+        Some(bundle_path) => {
+            for env in [TLS_CERT_ENV, TLS_PRIVATE_KEY_ENV] {
+                if std::env::var_os(env).is_some() {
+                    bail!(
+                        "Both --tls-bundle and ${} are set, only one is allowed",
+                        env
+                    );
+                }
+            }
+            let bundle = TlsBundle::read(&bundle_path)?;
+            // Serve the chain along with the certificate.
+            let cert = write_pem_file(&(bundle.cert + &bundle.chain), &mut pem_files)?;
+            let private_key = write_pem_file(&bundle.private_key, &mut pem_files)?;
+            let ca_pem = if args.ca_pem.is_none()
+                && std::env::var_os(TLS_CA_ENV).is_none()
+                && !bundle.chain.is_empty()
+            {
+                write_pem_file(&bundle.chain, &mut pem_files)?
+            } else {
+                tls_material_path(args.ca_pem, "ca-pem", TLS_CA_ENV, &mut pem_files)?
+            };
+            (cert, private_key, ca_pem)
+        }
+        None => (
+            tls_material_path(args.cert, "cert", TLS_CERT_ENV, &mut pem_files)?,
+            tls_material_path(
+                args.private_key,
+                "private-key",
+                TLS_PRIVATE_KEY_ENV,
+                &mut pem_files,
+            )?,
+            tls_material_path(args.ca_pem, "ca-pem", TLS_CA_ENV, &mut pem_files)?,
+        ),
+    };
+
     #[cfg(fbcode_build)]
     if let (Some(scs_cert_path), Some(scs_key_path)) =
         (&args.scs_client_cert, &args.scs_client_private_key)
     {
-        pushrebase_client::scs_override_certificate_paths(
-            scs_cert_path,
-            scs_key_path,
-            &args.ca_pem,
-        );
+        pushrebase_client::scs_override_certificate_paths(scs_cert_path, scs_key_path, &ca_pem);
     }
 
     #[cfg(fbcode_build)]
@@ -255,31 +1236,36 @@ fn main(fb: FacebookInit) -> Result<()> {
         pushrebase_client::land_service_override_certificate_paths(
             land_service_cert_path,
             land_service_key_path,
-            &args.ca_pem,
+            &ca_pem,
         );
     }
 
     let configs = app.repo_configs();
 
-    let acceptor = {
-        let mut builder = secure_utils::SslConfig::new(
-            args.ca_pem,
-            args.cert,
-            args.private_key,
-            args.ssl_ticket_seeds,
-        )
-        .tls_acceptor_builder(root_log.clone())
-        .context("Failed to instantiate TLS Acceptor builder")?;
-
-        builder.set_alpn_select_callback(|_, protos| {
-            // NOTE: Currently we do not support HTTP/2 here yet.
-            alpn::alpn_select(protos, alpn::HGCLI_ALPN)
-                .map_err(|_| AlpnError::ALERT_FATAL)?
-                .ok_or(AlpnError::NOACK)
-        });
-
-        builder.build()
+    let ocsp = args.ocsp_response.map(|path| {
+        let ocsp = OcspStapler::new(root_log.clone(), path, cert.clone(), ca_pem.clone());
+        runtime.spawn(ocsp.clone().reload_periodically());
+        ocsp
+    });
+    let tls_config = TlsConfig {
+        ca_pem,
+        cert,
+        private_key,
+        ssl_ticket_seeds: args.ssl_ticket_seeds,
+        alpn_protocols: args.alpn_protocols,
+        min_version: args.tls_min_version,
+        ciphers: args.tls_ciphers,
+        client_cert_mode: args.client_cert_mode,
+        ocsp,
     };
+    let acceptor = Arc::new(ArcSwap::from_pointee(tls_config.build_acceptor(&root_log)?));
+    info!(
+        root_log,
+        "Using TLS certificate {}",
+        cert_fingerprint(&acceptor.load())
+    );
+
+    let thrift_tls_acceptor = acceptor.clone();
 
     info!(root_log, "Creating repo listeners");
 
@@ -287,16 +1273,113 @@ fn main(fb: FacebookInit) -> Result<()> {
     let (terminate_sender, terminate_receiver) = oneshot::channel::<()>();
 
     let scribe = args.scribe_logging_args.get_scribe(fb)?;
-    let host_port = args.listening_host_port;
+    let tcp_options = TcpOptions {
+        ipv6_only: match (args.ipv6_only, args.dual_stack) {
+            (true, _) => Some(true),
+            (_, true) => Some(false),
+            _ => None,
+        },
+        reuse_port: args.reuse_port,
+        bind_retries: args.bind_retries,
+        bind_retry_interval: Duration::from_secs(args.bind_retry_interval),
+    };
+    let inherited_fds = systemd_listen_fds(
+        listen_pid.as_deref(),
+        listen_fds.as_deref(),
+        std::process::id(),
+    )?;
+    let mut sockets = if inherited_fds.is_empty() {
+        args.listening_host_port
+            .into_iter()
+            .map(|addr| ListeningSocket::Tcp {
+                addr,
+                options: tcp_options.clone(),
+                repo: None,
+            })
+            .collect::<Vec<_>>()
+    } else {
+        info!(
+            root_log,
+            "Using {} listening sockets passed by systemd",
+            inherited_fds.len()
+        );
+        inherited_fds
+            .into_iter()
+            .map(ListeningSocket::Inherited)
+            .collect()
+    };
+    for (repo, addr) in args.repo_listening_host_port {
+        match configs.repos.get(&repo) {
+            Some(config) if config.enabled => {}
+            _ => bail!(
+                "Cannot serve {} on {}: it is not an enabled repo",
+                repo,
+                addr
+            ),
+        }
+        sockets.push(ListeningSocket::Tcp {
+            addr,
+            options: tcp_options.clone(),
+            repo: Some(repo),
+        });
+    }
+    if let Some(path) = args.listening_uds {
+        sockets.push(ListeningSocket::Uds {
+            path,
+            tls: args.listening_uds_tls,
+        });
+    }
+
+    if sockets.is_empty() {
+        bail!("At least one of --listening-host-port or --listening-uds is required");
+    }
 
     let bound_addr_file = args.bound_address_file;
 
     let env = app.environment();
 
+    log_startup_summary(
+        &root_log,
+        &configs,
+        &sockets,
+        &acceptor.load(),
+        env.caching,
+        args.readonly.readonly,
+    );
+
     let scuba = env.scuba_sample_builder.clone();
 
     let will_exit = Arc::new(AtomicBool::new(false));
 
+    // Register for SIGHUP before serving, so that it doesn't terminate the process.
+    let (hangup, tls_hangup) = {
+        let _guard = runtime.enter();
+        (
+            signal(SignalKind::hangup()).context("Failed to register for SIGHUP")?,
+            signal(SignalKind::hangup()).context("Failed to register for SIGHUP")?,
+        )
+    };
+    let tls_config = Arc::new(tls_config);
+    if let (Some(path), Some(interval)) = (
+        tls_config.ssl_ticket_seeds.clone(),
+        args.ssl_ticket_seeds_reload_interval,
+    ) {
+        runtime.spawn(reload_ticket_seeds_periodically(
+            root_log.clone(),
+            tls_config.clone(),
+            acceptor.clone(),
+            path,
+            Duration::from_secs(interval),
+        ));
+    }
+    runtime.spawn(reload_tls_on_sighup(
+        root_log.clone(),
+        tls_config,
+        acceptor.clone(),
+        certs_reloadable,
+        tls_hangup,
+    ));
+
     let repo_listeners = {
         cloned!(root_log, service, will_exit, env, runtime);
         let app = Arc::clone(&app);
@@ -328,6 +1411,11 @@ fn main(fb: FacebookInit) -> Result<()> {
                 .try_collect()
                 .await?;
             info!(&root_log, "Cache warmup completed");
+            runtime.spawn(reload_configs_on_sighup(
+                app.clone(),
+                mononoke.clone(),
+                hangup,
+            ));
             if let Some(mut executor) = args.sharded_executor_args.build_executor(
                 app.fb,
                 runtime.clone(),
@@ -348,7 +1436,7 @@ fn main(fb: FacebookInit) -> Result<()> {
                 common,
                 mononoke.clone(),
                 root_log,
-                host_port,
+                sockets,
                 acceptor,
                 service,
                 terminate_receiver,
@@ -360,28 +1448,319 @@ fn main(fb: FacebookInit) -> Result<()> {
                 bound_addr_file,
                 env.acl_provider.as_ref(),
                 args.readonly.readonly,
+                args.expect_proxy_protocol,
+                args.max_connections,
+                args.connection_idle_timeout.map(Duration::from_secs),
+                KeepaliveOptions {
+                    idle: args.tcp_keepalive_idle.map(Duration::from_secs),
+                    interval: args.tcp_keepalive_interval.map(Duration::from_secs),
+                    probes: args.tcp_keepalive_probes,
+                },
             )
             .await
         }
     };
 
+    if let Some(port) = args.readiness_port {
+        let logger = root_log.clone();
+        let service = service.clone();
+        let will_exit = will_exit.clone();
+        runtime.spawn(async move {
+            if let Err(e) = serve_readiness(logger.clone(), port, service, will_exit).await {
+                error!(logger, "Failed to serve readiness: {:?}", e);
+            }
+        });
+    }
+
     // Thread with a thrift service is now detached
     let fb303_args = app.extension_args::<Fb303AppExtension>()?;
     fb303_args.start_fb303_server(fb, "mononoke_server", root_log, service)?;
 
+    if let Some(port) = args.thrift_tls_port {
+        let thrift_port = fb303_args
+            .fb303_thrift_port
+            .context("Serving thrift over TLS requires a thrift port")?;
+        let thrift_port = u16::try_from(thrift_port).context("Invalid thrift port")?;
+        runtime.block_on(ensure_thrift_port_is_local(thrift_port))?;
+        let logger = root_log.clone();
+        runtime.spawn(async move {
+            if let Err(e) =
+                serve_thrift_over_tls(logger.clone(), thrift_tls_acceptor, port, thrift_port).await
+            {
+                error!(logger, "Failed to serve thrift over TLS: {:?}", e);
+            }
+        });
+    }
+
+    // Depending on the args, we stop accepting connections either when signalled or after the
+    // grace period.
+    let terminate_sender = Mutex::new(Some(terminate_sender));
+    let stop_accepting = || {
+        if let Some(terminate_sender) = terminate_sender.lock().expect("poisoned lock").take() {
+            match terminate_sender.send(()) {
+                Err(err) => error!(root_log, "could not send termination signal: {:?}", err),
+                _ => {}
+            }
+        }
+    };
+    let drain_timeout = args.drain_timeout.map(Duration::from_secs);
+
     cmdlib::helpers::serve_forever(
         runtime,
         repo_listeners,
         root_log,
-        move || will_exit.store(true, Ordering::Relaxed),
+        move || {
+            will_exit.store(true, Ordering::Relaxed);
+            if args.stop_accepting_on_signal {
+                stop_accepting();
+            }
+        },
         args.shutdown_timeout_args.shutdown_grace_period,
         async {
-            match terminate_sender.send(()) {
-                Err(err) => error!(root_log, "could not send termination signal: {:?}", err),
-                _ => {}
+            stop_accepting();
+            let drain = repo_listener::wait_for_connections_closed(root_log);
+            match drain_timeout {
+                Some(drain_timeout) => {
+                    if tokio::time::timeout(drain_timeout, drain).await.is_err() {
+                        warn!(
+                            root_log,
+                            "Connections did not close within {:?}, dropping them", drain_timeout
+                        );
+                    }
+                }
+                None => drain.await,
             }
-            repo_listener::wait_for_connections_closed(root_log).await;
         },
         args.shutdown_timeout_args.shutdown_timeout,
     )
 }
+
+#[cfg(test)]
+mod test {
+    use metaconfig_parser::config::load_configs_from_raw;
+    use openssl::asn1::Asn1Time;
+    use openssl::ec::EcGroup;
+    use openssl::ec::EcKey;
+    use openssl::nid::Nid;
+    use openssl::x509::X509Name;
+    use repos::RawAllowlistIdentity;
+    use repos::RawBlobstoreConfig;
+    use repos::RawBlobstoreFilePath;
+    use repos::RawDbLocal;
+    use repos::RawMetadataConfig;
+    use repos::RawRepoConfig;
+    use repos::RawRepoConfigs;
+    use repos::RawRepoDefinition;
+    use repos::RawStorageConfig;
+
+    use super::*;
+
+    #[test]
+    fn test_dump_config() -> Result<()> {
+        let mut raw = RawRepoConfigs::default();
+        raw.storage.insert(
+            "blobstore".to_string(),
+            RawStorageConfig {
+                metadata: RawMetadataConfig::local(RawDbLocal {
+                    local_db_path: "/tmp/db".to_string(),
+                }),
+                blobstore: RawBlobstoreConfig::blob_files(RawBlobstoreFilePath {
+                    path: "/tmp/blobstore".to_string(),
+                }),
+                ephemeral_blobstore: None,
+            },
+        );
+        raw.repos.insert(
+            "repo".to_string(),
+            RawRepoConfig {
+                storage_config: Some("blobstore".to_string()),
+                ..Default::default()
+            },
+        );
+        raw.repo_definitions.repo_definitions.insert(
+            "repo".to_string(),
+            RawRepoDefinition {
+                repo_id: Some(1),
+                repo_name: Some("repo".to_string()),
+                repo_config: Some("repo".to_string()),
+                ..Default::default()
+            },
+        );
+        raw.common.redaction_config.blobstore = "blobstore".to_string();
+        raw.common.internal_identity = RawAllowlistIdentity {
+            identity_type: "SERVICE_IDENTITY".to_string(),
+            identity_data: "internal".to_string(),
+        };
+        let (configs, _) = load_configs_from_raw(raw)?;
+
+        let dumped: serde_json::Value = serde_json::from_str(&dump_config(&configs)?)?;
+        let repo = &dumped["repos"]["repo"];
+        // The storage the repo refers to by name is resolved.
+        assert_eq!(
+            repo["storage_config"]["blobstore"],
+            serde_json::json!({"Files": {"path": "/tmp/blobstore"}})
+        );
+        assert_eq!(
+            repo["storage_config"]["metadata"],
+            serde_json::json!({"Local": {"path": "/tmp/db"}})
+        );
+        // Unset fields get their defaults.
+        assert_eq!(repo["enabled"], true);
+        assert_eq!(repo["list_keys_patterns_max"], 500_000);
+        Ok(())
+    }
+
+    /// Format `addr` like the kernel does in `/proc/net`.
+    fn proc_net_addr(addr: IpAddr) -> String {
+        let octets = match addr {
+            IpAddr::V4(addr) => addr.octets().to_vec(),
+            IpAddr::V6(addr) => addr.octets().to_vec(),
+        };
+        octets
+            .chunks(4)
+            .map(|word| format!("{:08X}", u32::from_ne_bytes(word.try_into().unwrap())))
+            .collect()
+    }
+
+    #[test]
+    fn test_tcp_listen_addrs() -> Result<()> {
+        let loopback = IpAddr::from(Ipv4Addr::LOCALHOST);
+        let any = IpAddr::from(Ipv6Addr::UNSPECIFIED);
+        let table = format!(
+            "  sl  local_address rem_address   st tx_queue rx_queue\n\
+             0: {loopback}:1F90 00000000:0000 0A 00000000:00000000\n\
+             1: {any}:1F90 {any}:0000 0A 00000000:00000000\n\
+             2: {loopback}:0016 00000000:0000 0A 00000000:00000000\n\
+             3: {loopback}:1F90 {loopback}:D2F0 01 00000000:00000000\n",
+            loopback = proc_net_addr(loopback),
+            any = proc_net_addr(any),
+        );
+        // Only the sockets listening on port 8080.
+        assert_eq!(tcp_listen_addrs(&table, 8080)?, vec![loopback, any]);
+        assert_eq!(tcp_listen_addrs(&table, 22)?, vec![loopback]);
+        assert_eq!(tcp_listen_addrs(&table, 443)?, vec![]);
+
+        assert_eq!(
+            parse_proc_net_addr(&proc_net_addr(Ipv6Addr::LOCALHOST.into()))?,
+            IpAddr::from(Ipv6Addr::LOCALHOST)
+        );
+        assert!(parse_proc_net_addr("0100007").is_err());
+        assert!(parse_proc_net_addr("0100007G").is_err());
+        assert!(tcp_listen_addrs("header\n0: 0100007F\n", 8080).is_err());
+        Ok(())
+    }
+
+    /// A self-signed certificate for `name` and its private key, both in PEM.
+    fn cert_and_key(name: &str) -> Result<(String, String)> {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1)?;
+        let key = PKey::from_ec_key(EcKey::generate(&group)?)?;
+        let mut subject = X509Name::builder()?;
+        subject.append_entry_by_text("CN", name)?;
+        let subject = subject.build();
+        let mut cert = X509::builder()?;
+        cert.set_version(2)?;
+        cert.set_subject_name(&subject)?;
+        cert.set_issuer_name(&subject)?;
+        cert.set_pubkey(&key)?;
+        cert.set_not_before(&*Asn1Time::days_from_now(0)?)?;
+        cert.set_not_after(&*Asn1Time::days_from_now(1)?)?;
+        cert.sign(&key, MessageDigest::sha256())?;
+        Ok((
+            String::from_utf8(cert.build().to_pem()?)?,
+            String::from_utf8(key.private_key_to_pem_pkcs8()?)?,
+        ))
+    }
+
+    fn read_bundle(blocks: &[&str]) -> Result<TlsBundle> {
+        let mut file = NamedTempFile::new()?;
+        file.write_all(blocks.concat().as_bytes())?;
+        TlsBundle::read(file.path().to_str().unwrap())
+    }
+
+    fn read_bundle_error(blocks: &[&str]) -> Result<String> {
+        match read_bundle(blocks) {
+            Ok(_) => bail!("TLS bundle unexpectedly valid"),
+            Err(e) => Ok(format!("{:#}", e)),
+        }
+    }
+
+    #[test]
+    fn test_tls_bundle() -> Result<()> {
+        let (cert, key) = cert_and_key("server")?;
+        let (intermediate, _) = cert_and_key("intermediate")?;
+        let (root, _) = cert_and_key("root")?;
+
+        let bundle = read_bundle(&[&cert, &intermediate, &root, &key])?;
+        assert_eq!(bundle.cert, cert);
+        assert_eq!(bundle.chain, format!("{}{}", intermediate, root));
+        assert_eq!(bundle.private_key, key);
+
+        // The private key may come first, but the server certificate is always the first one.
+        let bundle = read_bundle(&[&key, &cert, &intermediate])?;
+        assert_eq!(bundle.cert, cert);
+        assert_eq!(bundle.chain, intermediate);
+        assert_eq!(bundle.private_key, key);
+
+        // Text outside of the PEM blocks is ignored.
+        let bundle = read_bundle(&["subject=CN = server\n", &cert, "\n", &key])?;
+        assert_eq!(bundle.cert, cert);
+        assert_eq!(bundle.chain, "");
+        Ok(())
+    }
+
+    #[test]
+    fn test_tls_bundle_invalid() -> Result<()> {
+        let (cert, key) = cert_and_key("server")?;
+        let (other_cert, other_key) = cert_and_key("other")?;
+
+        assert!(read_bundle_error(&[&cert])?.contains("has no private key"));
+        assert!(read_bundle_error(&[&key])?.contains("has no certificate"));
+        assert!(read_bundle_error(&[&cert, &key, &other_key])?.contains("several private keys"));
+        assert!(read_bundle_error(&[&cert, &other_key])?.contains("does not match"));
+        // The first certificate is the server certificate, whatever the key.
+        assert!(read_bundle_error(&[&other_cert, &cert, &key])?.contains("does not match"));
+
+        // A block that doesn't end before the next one, or before the end of the file.
+        let unterminated = cert.replace("-----END CERTIFICATE-----\n", "");
+        assert!(read_bundle_error(&[&unterminated, &key])?.contains("Unterminated CERTIFICATE"));
+        let unterminated = key.replace("-----END PRIVATE KEY-----\n", "");
+        assert!(read_bundle_error(&[&cert, &unterminated])?.contains("Unterminated PRIVATE KEY"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_systemd_listen_fds() -> Result<()> {
+        let fds = |listen_pid: Option<&str>, listen_fds: Option<&str>| {
+            systemd_listen_fds(listen_pid.map(OsStr::new), listen_fds.map(OsStr::new), 42)
+        };
+        assert_eq!(fds(None, None)?, Vec::<RawFd>::new());
+        assert_eq!(fds(Some("42"), Some("2"))?, vec![3, 4]);
+        assert_eq!(fds(Some("42"), Some("0"))?, Vec::<RawFd>::new());
+        // The sockets were passed to another process, whatever they are.
+        assert_eq!(fds(Some("43"), Some("2"))?, Vec::<RawFd>::new());
+        assert_eq!(fds(Some("43"), None)?, Vec::<RawFd>::new());
+
+        assert!(fds(Some("pid"), Some("2")).is_err());
+        assert!(fds(Some("42"), None).is_err());
+        assert!(fds(Some("42"), Some("two")).is_err());
+        assert!(fds(Some("42"), Some("-1")).is_err());
+        assert!(fds(Some("42"), Some(&RawFd::MAX.to_string())).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_pem_boundary() {
+        assert_eq!(
+            pem_boundary("-----BEGIN CERTIFICATE-----", "BEGIN"),
+            Some("CERTIFICATE")
+        );
+        assert_eq!(
+            pem_boundary("-----END RSA PRIVATE KEY-----", "END"),
+            Some("RSA PRIVATE KEY")
+        );
+        assert_eq!(pem_boundary("-----BEGIN CERTIFICATE-----", "END"), None);
+        assert_eq!(pem_boundary("-----BEGIN CERTIFICATE", "BEGIN"), None);
+        assert_eq!(pem_boundary("-----BEGINCERTIFICATE-----", "BEGIN"), None);
+        assert_eq!(pem_boundary("MIIBszCCAVmgAwIBAgIU", "BEGIN"), None);
+    }
+}
@@ -0,0 +1,198 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use arc_swap::ArcSwapOption;
+use openssl::error::ErrorStack;
+use openssl::hash::MessageDigest;
+use openssl::ocsp::OcspCertId;
+use openssl::ocsp::OcspResponse;
+use openssl::ocsp::OcspResponseStatus;
+use openssl::ssl::SslRef;
+use openssl::x509::X509VerifyResult;
+use openssl::x509::X509;
+use slog::warn;
+use slog::Logger;
+
+const RELOAD_INTERVAL: Duration = Duration::from_secs(60);
+/// How far off the clocks of the OCSP responder and ours can be.
+const MAX_CLOCK_SKEW_SECS: u32 = 300;
+
+/// Staples the OCSP response from a file to TLS handshakes, as long as it is a current response
+/// for the server certificate.
+pub struct OcspStapler {
+    logger: Logger,
+    path: PathBuf,
+    cert_path: String,
+    ca_path: String,
+    response: ArcSwapOption<Vec<u8>>,
+}
+
+impl OcspStapler {
+    /// The issuer of the certificate in `cert_path` is looked up in the chain following it, and
+    /// then in the certificates in `ca_path`.
+    pub fn new(logger: Logger, path: PathBuf, cert_path: String, ca_path: String) -> Arc<Self> {
+        let stapler = Arc::new(Self {
+            logger,
+            path,
+            cert_path,
+            ca_path,
+            response: ArcSwapOption::empty(),
+        });
+        stapler.reload();
+        stapler
+    }
+
+    /// Reload the response from its file. Without a valid response, handshakes go on without
+    /// stapling.
+    pub fn reload(&self) {
+        match self.load() {
+            Ok(response) => self.response.store(Some(Arc::new(response))),
+            Err(e) => {
+                warn!(self.logger, "Not stapling OCSP responses: {:#}", e);
+                self.response.store(None);
+            }
+        }
+    }
+
+    fn load(&self) -> Result<Vec<u8>> {
+        let der = std::fs::read(&self.path)
+            .with_context(|| format!("Failed to read {}", self.path.display()))?;
+        let response = OcspResponse::from_der(&der).context("Invalid OCSP response")?;
+        if response.status() != OcspResponseStatus::SUCCESSFUL {
+            bail!(
+                "OCSP response has unsuccessful status {}",
+                response.status().as_raw()
+            );
+        }
+
+        // The certificate may have been rotated since the last time.
+        let cert_id = self.cert_id()?;
+        let basic = response.basic().context("Invalid OCSP response")?;
+        let status = basic
+            .find_status(&cert_id)
+            .context("OCSP response is not for the server certificate")?;
+        status
+            .check_validity(MAX_CLOCK_SKEW_SECS, None)
+            .context("OCSP response is stale")?;
+
+        Ok(der)
+    }
+
+    fn cert_id(&self) -> Result<OcspCertId> {
+        let certs = read_certs(&self.cert_path)?;
+        let (cert, chain) = certs
+            .split_first()
+            .with_context(|| format!("No certificate in {}", self.cert_path))?;
+        let cas = read_certs(&self.ca_path)?;
+        let issuer = chain
+            .iter()
+            .chain(cas.iter())
+            .find(|issuer| issuer.issued(cert) == X509VerifyResult::OK)
+            .context("Failed to find the issuer of the server certificate")?;
+        Ok(OcspCertId::from_cert(MessageDigest::sha1(), cert, issuer)?)
+    }
+
+    pub async fn reload_periodically(self: Arc<Self>) {
+        loop {
+            tokio::time::sleep(RELOAD_INTERVAL).await;
+            self.reload();
+        }
+    }
+
+    /// Meant to be the status callback of the TLS acceptor.
+    pub fn staple(&self, ssl: &mut SslRef) -> Result<bool, ErrorStack> {
+        match self.response.load_full() {
+            Some(response) => {
+                ssl.set_ocsp_status(&response)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+fn read_certs(path: &str) -> Result<Vec<X509>> {
+    let pem = std::fs::read(path).with_context(|| format!("Failed to read {}", path))?;
+    X509::stack_from_pem(&pem).with_context(|| format!("Invalid certificates in {}", path))
+}
+
+#[cfg(test)]
+mod test {
+    use slog::o;
+    use slog::Discard;
+    use tempfile::TempDir;
+
+    use super::*;
+
+    // Generated with fixtures/ocsp/gen.sh.
+    const CA: &[u8] = include_bytes!("fixtures/ocsp/ca.crt");
+    const CERT: &[u8] = include_bytes!("fixtures/ocsp/server.crt");
+    const RESPONSE: &[u8] = include_bytes!("fixtures/ocsp/server.der");
+    const OTHER_CERT_RESPONSE: &[u8] = include_bytes!("fixtures/ocsp/other.der");
+    const STALE_RESPONSE: &[u8] = include_bytes!("fixtures/ocsp/stale.der");
+    // An OCSPResponse with the tryLater status, which has no response bytes.
+    const TRY_LATER_RESPONSE: &[u8] = &[0x30, 0x03, 0x0a, 0x01, 0x03];
+
+    /// A stapler of the response in `dir`, which is written from `response` if any.
+    fn stapler(dir: &TempDir, response: Option<&[u8]>) -> Result<Arc<OcspStapler>> {
+        let path = dir.path().join("ocsp.der");
+        if let Some(response) = response {
+            std::fs::write(&path, response)?;
+        }
+        let cert_path = dir.path().join("server.crt");
+        std::fs::write(&cert_path, CERT)?;
+        let ca_path = dir.path().join("ca.crt");
+        std::fs::write(&ca_path, CA)?;
+        Ok(OcspStapler::new(
+            Logger::root(Discard, o!()),
+            path,
+            cert_path.to_str().unwrap().to_string(),
+            ca_path.to_str().unwrap().to_string(),
+        ))
+    }
+
+    fn load_error(response: Option<&[u8]>) -> Result<String> {
+        let dir = TempDir::new()?;
+        let stapler = stapler(&dir, response)?;
+        // Handshakes go on without stapling.
+        assert!(stapler.response.load().is_none());
+        Ok(format!("{:#}", stapler.load().unwrap_err()))
+    }
+
+    #[test]
+    fn test_load() -> Result<()> {
+        let dir = TempDir::new()?;
+        let stapler = stapler(&dir, Some(RESPONSE))?;
+        assert_eq!(
+            stapler.response.load().as_deref().map(Vec::as_slice),
+            Some(RESPONSE)
+        );
+
+        // A response that turns invalid stops being stapled.
+        std::fs::write(&stapler.path, STALE_RESPONSE)?;
+        stapler.reload();
+        assert!(stapler.response.load().is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_rejected() -> Result<()> {
+        assert!(load_error(None)?.starts_with("Failed to read"));
+        assert!(load_error(Some(b"garbage"))?.starts_with("Invalid OCSP response"));
+        assert!(load_error(Some(TRY_LATER_RESPONSE))?.contains("unsuccessful status 3"));
+        assert!(load_error(Some(OTHER_CERT_RESPONSE))?.contains("not for the server certificate"));
+        assert!(load_error(Some(STALE_RESPONSE))?.starts_with("OCSP response is stale"));
+        Ok(())
+    }
+}
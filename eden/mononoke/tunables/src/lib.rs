@@ -341,6 +341,10 @@ pub struct MononokeTunables {
     disable_sql_auto_retries: AtomicBool,
     // Disable SQL queries being cached using `cacheable` keyword
     disable_sql_auto_cache: AtomicBool,
+
+    // Rebuild the replication lag monitors of wait_for_replication when the
+    // storage config changes, instead of only on restart
+    wait_for_replication_reload_monitors: AtomicBool,
 }
 
 fn log_tunables(tunables: &TunablesStruct) -> String {